@@ -0,0 +1,237 @@
+use ixl::interp::{Context, CommandRegistry, Value, Nil, Str, Num, Closure};
+use ixl::interp::{RuntimeError, BadArguments};
+use ixl::interp::native;
+use ixl::dotenv::load_dotenv;
+
+/**
+ * The builtins every registry starts out with
+ */
+pub fn default_registry() -> CommandRegistry {
+  let registry = CommandRegistry();
+  registry.register("echo", native(echo));
+  registry.register("set", native(set));
+  registry.register("timeout", native(timeout));
+  registry.register("dotenv", native(dotenv));
+  registry
+}
+
+// echo [-n] args...: writes its arguments separated by spaces, with a
+// newline unless -n is given. with no arguments, it writes its input.
+extern fn echo(ctx: &mut Context, flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  if args.is_empty() {
+    match ctx.input {
+      Some(ref input) => { ctx.out.write_str(input.to_str()); }
+      None => {}
+    }
+  }
+  else {
+    let words = args.map(|arg| arg.to_str());
+    ctx.out.write_str(str::connect(words, " "));
+  }
+
+  if !flags.contains(&~"n") { ctx.out.write_char('\n'); }
+
+  Ok(Nil)
+}
+
+// set <name> <value>: sets a variable in the current scope
+extern fn set(ctx: &mut Context, _flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  if args.len() != 2u {
+    return Err(BadArguments(~"set", ~"expected a name and a value"));
+  }
+
+  ctx.env.set(args[0].to_str(), copy args[1]);
+  Ok(Nil)
+}
+
+// timeout <duration> [block]: runs the block, failing with TimedOut if
+// it's still running after the duration. the check happens before each
+// command starts, so a single long-running builtin isn't interrupted.
+extern fn timeout(ctx: &mut Context, _flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  let usage = Err(BadArguments(~"timeout", ~"expected a duration and a block"));
+  if args.len() != 2u { return usage; }
+
+  let ns = match args[0] {
+    Num(n) => (n as u64) * 1_000_000_000u64,
+    Str(ref s) => match parse_duration(*s) {
+      Some(ns) => ns,
+      None => {
+        return Err(BadArguments(~"timeout", fmt!("bad duration `%s`", *s)));
+      }
+    },
+    _ => { return usage; }
+  };
+
+  match args[1] {
+    Closure(commands) => ctx.eval_with_timeout(commands, ns),
+    _ => usage
+  }
+}
+
+// parses a duration like `30s`, `500ms` or `2m` into nanoseconds. a
+// bare number is in seconds. durations too long for a u64 are errors
+// rather than wrapping around.
+pub fn parse_duration(s: &str) -> Option<u64> {
+  // only ascii digits: char::is_digit takes any unicode digit, and
+  // the subtraction below would turn those into nonsense numbers
+  let digits = str::find(s, |ch| !('0' <= ch && ch <= '9'))
+    .get_default(s.len());
+  if digits == 0u { return None; }
+
+  let mut n = 0u64;
+
+  for str::each_char(s.slice(0u, digits)) |ch| {
+    let digit = (ch as u64) - ('0' as u64);
+    if n > (u64::max_value - digit) / 10u64 { return None; }
+    n = n * 10u64 + digit;
+  }
+
+  let unit = match s.slice(digits, s.len()) {
+    ~"" | ~"s" => 1_000_000_000u64,
+    ~"ms" => 1_000_000u64,
+    ~"m" => 60_000_000_000u64,
+    _ => { return None; }
+  };
+
+  if n > u64::max_value / unit { return None; }
+  Some(n * unit)
+}
+
+// dotenv [--local] <file>...: sets environment variables from dotenv
+// files, or with --local, variables in the current scope. a malformed
+// file sets nothing.
+extern fn dotenv(ctx: &mut Context, flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  if args.is_empty() {
+    return Err(BadArguments(~"dotenv", ~"expected a file"));
+  }
+
+  for args.each |arg| {
+    match load_dotenv(&Path(arg.to_str())) {
+      Ok(vars) => {
+        for vars.each |&(name, value)| {
+          if flags.contains(&~"local") { ctx.env.set(name, Str(value)); }
+          else { os::setenv(name, value); }
+        }
+      }
+      Err(msg) => { return Err(BadArguments(~"dotenv", msg)); }
+    }
+  }
+
+  Ok(Nil)
+}
+
+#[cfg(test)]
+extern fn answer(_ctx: &mut Context, _flags: &[~str], _args: &[Value])
+    -> Result<Value, RuntimeError> {
+  Ok(Num(42))
+}
+
+// spins for the given number of milliseconds, so the timeout checks
+// between commands have something to catch
+#[cfg(test)]
+extern fn spin(_ctx: &mut Context, _flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  use std::time;
+
+  let ms = match args[0] { Num(n) => n as u64, _ => fail ~"spin: bad ms" };
+  let until = time::precise_time_ns() + ms * 1_000_000u64;
+  while time::precise_time_ns() < until {}
+  Ok(Nil)
+}
+
+#[test]
+fn test_echo() {
+  use ixl::interp::run;
+
+  let (result, output) = run("echo a 'b 3; echo -n \"{c d}", default_registry());
+  assert(result.is_ok());
+  assert(output == ~"a b 3\nc d");
+
+  let registry = default_registry();
+  registry.register("answer", native(answer));
+
+  let (result, output) = run("answer | echo; echo", registry);
+  assert(result.is_ok());
+  assert(output == ~"42\n\n");
+}
+
+#[test]
+fn test_parse_duration() {
+  assert(parse_duration("30s") == Some(30_000_000_000u64));
+  assert(parse_duration("30") == Some(30_000_000_000u64));
+  assert(parse_duration("250ms") == Some(250_000_000u64));
+  assert(parse_duration("2m") == Some(120_000_000_000u64));
+  assert(parse_duration("") == None);
+  assert(parse_duration("s") == None);
+  assert(parse_duration("3h") == None);
+  assert(parse_duration("1.5s") == None);
+  assert(parse_duration("1\u00bds") == None);
+  assert(parse_duration("\u0663s") == None);
+
+  assert(parse_duration("18446744073709551615ms") == None);
+  assert(parse_duration("18446744073709551616") == None);
+  assert(parse_duration("99999999999999999999999999") == None);
+  assert(parse_duration("18446744073s") == Some(18446744073000000000u64));
+  assert(parse_duration("18446744074s") == None);
+}
+
+#[test]
+fn test_timeout() {
+  use ixl::interp::{run, TimedOut};
+
+  let (result, output) = run("timeout 10s [echo a; echo b]", default_registry());
+  assert(result == Ok(Nil));
+  assert(output == ~"a\nb\n");
+
+  // a zero timeout has already run out by the time the block starts
+  let (result, output) = run("timeout 0ms [echo a]\necho b", default_registry());
+  assert(result == Err(TimedOut));
+  assert(output == ~"");
+
+  // an outer timeout still applies inside a longer inner one: the
+  // inner block starts in time, but runs past the outer deadline
+  let registry = default_registry();
+  registry.register("spin", native(spin));
+
+  let (result, output) =
+    run("timeout 200ms [timeout 10s [echo a; spin 400; echo b]]", registry);
+  assert(result == Err(TimedOut));
+  assert(output == ~"a\n");
+
+  // a deadline past the end of the clock waits forever, rather than
+  // wrapping around into the past
+  let (result, output) = run("timeout 18446744073s [echo a]",
+                             default_registry());
+  assert(result == Ok(Nil));
+  assert(output == ~"a\n");
+
+  let (result, _) = run("timeout 18446744074s [echo a]", default_registry());
+  assert(result == Err(BadArguments(~"timeout",
+                                    ~"bad duration `18446744074s`")));
+
+  let (result, _) = run("timeout soon [echo a]", default_registry());
+  assert(result == Err(BadArguments(~"timeout", ~"bad duration `soon`")));
+}
+
+#[test]
+fn test_set() {
+  use ixl::interp::{run, UnknownVariable};
+
+  let (result, output) = run("set a 1; set b x; echo $a \"{$b/$a}",
+                             default_registry());
+  assert(result == Ok(Nil));
+  assert(output == ~"1 x/1\n");
+
+  // blocks get their own scope
+  let source = "set a 1; timeout 10s [set a 2; set b 3; echo $a $b]; echo $a";
+  let (result, output) = run(source, default_registry());
+  assert(result == Ok(Nil));
+  assert(output == ~"2 3\n1\n");
+
+  let (result, _) = run("timeout 10s [set b 3]; echo $b", default_registry());
+  assert(result == Err(UnknownVariable(~"b")));
+}
@@ -0,0 +1,176 @@
+/**
+ * Dotenv files
+ *
+ * Each line is blank, a `#` comment, or `[export] KEY=value`.  Values
+ * may be bare (running to the end of the line or a ` #` comment, with
+ * surrounding spaces trimmed), single-quoted (taken literally), or
+ * double-quoted (where `\n`, `\t`, `\r`, `\"`, `\\` and `\$` are
+ * escapes).  Quoted values can't span lines.
+ */
+pub fn parse_dotenv(source: &str) -> Result<~[(~str, ~str)], ~str> {
+  let mut vars = ~[];
+  let mut line_no = 0u;
+
+  for str::each_line_any(source) |line| {
+    line_no += 1u;
+
+    match parse_line(str::chars(line)) {
+      Ok(Some(var)) => { vars.push(var); }
+      Ok(None) => {}
+      Err(msg) => { return Err(fmt!("line %u: %s", line_no, msg)); }
+    }
+  }
+
+  Ok(vars)
+}
+
+// reads a dotenv file, prefixing any error with the file's name
+pub fn load_dotenv(path: &Path) -> Result<~[(~str, ~str)], ~str> {
+  let source = match io::read_whole_file_str(path) {
+    Ok(source) => source,
+    Err(msg) => { return Err(msg); }
+  };
+
+  match parse_dotenv(source) {
+    Ok(vars) => Ok(vars),
+    Err(msg) => Err(fmt!("%s:%s", path.to_str(), msg))
+  }
+}
+
+fn parse_line(line: ~[char]) -> Result<Option<(~str, ~str)>, ~str> {
+  let mut i = skip_spaces(line, 0u);
+  if i == line.len() || line[i] == '#' { return Ok(None); }
+
+  if starts_with(line, i, "export") && i + 6u < line.len()
+     && is_space(line[i + 6u]) {
+    i = skip_spaces(line, i + 6u);
+  }
+
+  let start = i;
+  while i < line.len() && is_name_char(line[i]) { i += 1u; }
+
+  if i == start || char::is_digit(line[start]) {
+    return Err(~"expected a variable name");
+  }
+
+  let name = str::from_chars(vec::slice(line, start, i));
+
+  i = skip_spaces(line, i);
+  if i == line.len() || line[i] != '=' {
+    return Err(fmt!("expected `=` after `%s`", name));
+  }
+  i = skip_spaces(line, i + 1u);
+
+  let value = if i < line.len() && (line[i] == '\'' || line[i] == '"') {
+    let quote = line[i];
+    let mut value = ~"";
+    i += 1u;
+
+    loop {
+      if i == line.len() {
+        return Err(fmt!("unterminated quote in `%s`", name));
+      }
+
+      let ch = line[i];
+      i += 1u;
+
+      if ch == quote { break; }
+
+      if ch == '\\' && quote == '"' && i < line.len() {
+        let escaped = line[i];
+        i += 1u;
+
+        match escaped {
+          'n' => { str::push_char(&mut value, '\n'); }
+          't' => { str::push_char(&mut value, '\t'); }
+          'r' => { str::push_char(&mut value, '\r'); }
+          '"' | '\\' | '$' => { str::push_char(&mut value, escaped); }
+          _ => {
+            return Err(fmt!("unknown escape `\\%c` in `%s`", escaped, name));
+          }
+        }
+      }
+      else {
+        str::push_char(&mut value, ch);
+      }
+    }
+
+    i = skip_spaces(line, i);
+    if i < line.len() && line[i] != '#' {
+      return Err(fmt!("unexpected text after the value of `%s`", name));
+    }
+
+    value
+  }
+  else {
+    let start = i;
+
+    while i < line.len() {
+      if line[i] == '#' && is_space(line[i - 1u]) { break; }
+      i += 1u;
+    }
+
+    str::trim_right(str::from_chars(vec::slice(line, start, i)))
+  };
+
+  Ok(Some((name, value)))
+}
+
+fn skip_spaces(line: &[char], i: uint) -> uint {
+  let mut i = i;
+  while i < line.len() && is_space(line[i]) { i += 1u; }
+  i
+}
+
+fn starts_with(line: &[char], i: uint, word: &str) -> bool {
+  let mut j = i;
+
+  for str::each_char(word) |ch| {
+    if j == line.len() || line[j] != ch { return false; }
+    j += 1u;
+  }
+
+  true
+}
+
+pure fn is_space(ch: char) -> bool {
+  " \t".contains_char(ch)
+}
+
+pure fn is_name_char(ch: char) -> bool {
+  ch == '_' || char::is_ascii(ch) && char::is_alphanumeric(ch)
+}
+
+#[test]
+fn test_parse_dotenv() {
+  let source = "# settings\n\
+                \n\
+                HOST=localhost\n\
+                export PORT = 8080 # the default\n\
+                EMPTY=\n\
+                URL=http://x/#anchor\n\
+                LITERAL='a \\n $b'\n\
+                QUOTED=\"a\\tb \\\"c\\\" \\$d\"  # note\r\n";
+
+  assert(parse_dotenv(source) == Ok(~[
+    (~"HOST", ~"localhost"),
+    (~"PORT", ~"8080"),
+    (~"EMPTY", ~""),
+    (~"URL", ~"http://x/#anchor"),
+    (~"LITERAL", ~"a \\n $b"),
+    (~"QUOTED", ~"a\tb \"c\" $d"),
+  ]));
+}
+
+#[test]
+fn test_parse_dotenv_errors() {
+  assert(parse_dotenv("A=1\nnot a var") ==
+    Err(~"line 2: expected `=` after `not`"));
+  assert(parse_dotenv("=1") == Err(~"line 1: expected a variable name"));
+  assert(parse_dotenv("1A=1") == Err(~"line 1: expected a variable name"));
+  assert(parse_dotenv("A='x") == Err(~"line 1: unterminated quote in `A`"));
+  assert(parse_dotenv("A=\"\\q\"") ==
+    Err(~"line 1: unknown escape `\\q` in `A`"));
+  assert(parse_dotenv("A='x' y") ==
+    Err(~"line 1: unexpected text after the value of `A`"));
+}
@@ -0,0 +1,88 @@
+use std::map::HashMap;
+
+use ixl::interp::Value;
+
+/**
+ * Variable environments
+ *
+ * A stack of scopes, innermost last.  The interpreter pushes a scope
+ * for each block it runs, so variables set inside a block go away when
+ * it finishes, and lookups fall back through the enclosing scopes to
+ * the global one at the bottom, which is never popped.
+ */
+pub struct Env {
+  mut scopes: ~[HashMap<~str, Value>],
+}
+
+pub fn Env() -> Env {
+  Env { scopes: ~[HashMap()] }
+}
+
+impl Env {
+  fn push_scope(&self) {
+    self.scopes.push(HashMap());
+  }
+
+  fn pop_scope(&self) {
+    if self.scopes.len() == 1u { fail ~"ixl: can't pop the global scope"; }
+    self.scopes.pop();
+  }
+
+  fn depth(&self) -> uint {
+    self.scopes.len()
+  }
+
+  // looks a name up in the innermost scope that defines it
+  fn get(&self, name: &str) -> Option<Value> {
+    let mut i = self.scopes.len();
+
+    while i > 0u {
+      i -= 1u;
+
+      match self.scopes[i].find(name.to_owned()) {
+        Some(value) => { return Some(value); }
+        None => {}
+      }
+    }
+
+    None
+  }
+
+  // defines a name in the current scope, shadowing any outer definition
+  fn set(&self, name: &str, value: Value) {
+    self.scopes.last().insert(name.to_owned(), value);
+  }
+
+  // defines a name in the global scope
+  fn set_global(&self, name: &str, value: Value) {
+    self.scopes[0].insert(name.to_owned(), value);
+  }
+}
+
+#[test]
+fn test_scopes() {
+  use ixl::interp::{Str, Num};
+
+  let env = Env();
+  env.set("a", Num(1));
+  env.set("b", Num(2));
+
+  env.push_scope();
+  env.set("b", Str(~"inner"));
+  env.set_global("c", Num(3));
+  assert(env.depth() == 2u);
+  assert(env.get("a") == Some(Num(1)));
+  assert(env.get("b") == Some(Str(~"inner")));
+  assert(env.get("c") == Some(Num(3)));
+
+  env.pop_scope();
+  assert(env.get("b") == Some(Num(2)));
+  assert(env.get("c") == Some(Num(3)));
+  assert(env.get("d") == None);
+}
+
+#[test]
+#[should_fail]
+fn test_pop_global() {
+  Env().pop_scope();
+}
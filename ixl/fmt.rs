@@ -0,0 +1,502 @@
+use std::map::HashMap;
+
+use ixl::parser::{Program, Command, Component, Flag, Argument};
+use ixl::parser::{Term, Block, Subst, Variable, String, Interp, NumberLiteral};
+use ixl::parser::{number_value, is_word_terminator};
+use ixl::parser::{ParserOptions, default_options, scanner_with_options};
+
+/**
+ * The formatter
+ *
+ * Prints a Program back out as canonical ixl source: one command per
+ * line, single spaces between components, pipelines on one line, and
+ * blocks of more than one command broken over indented lines.  Quoting
+ * is the lightest form that reads back as the same term.
+ *
+ * The output reads back under the parser options in the FormatRules
+ * (the defaults, for format_program), which matter here for their
+ * comment syntax.  Comments aren't part of the AST, so they are
+ * dropped, as are empty commands.
+ */
+pub fn format_program(program: &Program) -> ~str {
+  format_program_with(program, &FormatRules())
+}
+
+// parses and formats source, refusing (rather than dropping them) if
+// it has comments or %if directives, which the AST doesn't keep
+pub fn format_source(reader: io::Reader,
+                     rules: &FormatRules) -> Result<~str, ~str> {
+  let scanner = scanner_with_options(reader, copy rules.options);
+  let program = scanner.parse();
+
+  if scanner.saw_conditional {
+    Err(~"can't format a script with %if directives \
+          (the branches not taken would be lost)")
+  }
+  else if scanner.saw_comment {
+    Err(~"can't format a script with comments (they would be lost)")
+  }
+  else {
+    Ok(format_program_with(&program, rules))
+  }
+}
+
+/**
+ * Formatting rules
+ *
+ * Host commands can register a rule for formatting their own
+ * invocations, say to keep a block argument on one line.  A rule gets
+ * the whole command (less any pipe) and returns its text, or None to
+ * leave it to the formatter.  Rules can call format_term to format the
+ * parts they don't care about.
+ *
+ * The rules also carry the options the output will be parsed with, so
+ * that words aren't written in ways that would read as comments.
+ */
+pub type FormatRule = extern fn(command: &Command, indent: uint,
+                                rules: &FormatRules) -> Option<~str>;
+
+pub struct FormatRules {
+  rules: HashMap<~str, FormatRule>,
+  options: ParserOptions,
+}
+
+pub fn FormatRules() -> FormatRules {
+  format_rules_with_options(default_options())
+}
+
+pub fn format_rules_with_options(options: ParserOptions) -> FormatRules {
+  FormatRules { rules: HashMap(), options: options }
+}
+
+impl FormatRules {
+  // registers a rule for a command, replacing any existing one
+  fn register(&self, name: &str, rule: FormatRule) {
+    self.rules.insert(name.to_owned(), rule);
+  }
+
+  // the rule for a command, going by its first argument when that's
+  // a plain word
+  fn find(&self, command: &Command) -> Option<FormatRule> {
+    if command.components.is_empty() { return None; }
+
+    match *command.components[0] {
+      Argument(String(ref name)) => self.rules.find(copy *name),
+      Argument(Interp([String(ref name)])) => self.rules.find(copy *name),
+      _ => None
+    }
+  }
+
+  // whether `ch` ends a bareword or a word after `'` or `-`
+  fn ends_word(&self, ch: char) -> bool {
+    is_word_terminator(ch) || vec::any(self.options.line_comments, |c| {
+      c.len() == 1u && c[0] as char == ch
+    })
+  }
+
+  // whether a word written as `s` would be read as a comment
+  fn starts_comment(&self, s: &str) -> bool {
+    let block = match self.options.block_comments {
+      Some((ref open, _)) => s.starts_with(*open),
+      None => false
+    };
+
+    block || vec::any(self.options.line_comments, |c| s.starts_with(*c))
+  }
+}
+
+pub fn format_program_with(program: &Program, rules: &FormatRules) -> ~str {
+  let mut out = ~"";
+
+  // the pragma is needed whenever the output would otherwise be read
+  // back as a different version
+  if program.version != rules.options.version {
+    out += fmt!("%%ixl %u\n", program.version);
+  }
+
+  for program.commands.each |command| {
+    if !is_empty(*command) {
+      out += format_command(*command, 0u, rules);
+      out += "\n";
+    }
+  }
+
+  out
+}
+
+fn format_command(command: &Command, indent: uint,
+                  rules: &FormatRules) -> ~str {
+  let custom = match rules.find(command) {
+    Some(rule) => rule(command, indent, rules),
+    None => None
+  };
+
+  let mut out = match custom {
+    Some(text) => text,
+    None => format_words(command, indent, rules)
+  };
+
+  match command.pipe {
+    Some(pipe) => { out += " | " + format_command(pipe, indent, rules); }
+    None => {}
+  }
+
+  out
+}
+
+// the target and components of a command
+fn format_words(command: &Command, indent: uint,
+                rules: &FormatRules) -> ~str {
+  let mut words = ~[];
+
+  match command.target {
+    Some(ref target) => {
+      words.push(~"@" + format_term(target, indent, rules));
+    }
+    None => {}
+  }
+
+  for command.components.each |component| {
+    words.push(format_component(*component, indent, rules));
+  }
+
+  str::connect(words, " ")
+}
+
+fn format_component(component: &Component, indent: uint,
+                    rules: &FormatRules) -> ~str {
+  match *component {
+    Flag(ref name) => {
+      let flag = if is_plain_word(*name, rules) && name.len() == 1u &&
+                    *name != ~"-" {
+        ~"-" + *name
+      }
+      else if is_plain_word(*name, rules) {
+        ~"--" + *name
+      }
+      else {
+        ~"--{" + escape(*name, "{}") + "}"
+      };
+
+      // say, with `--` comments
+      if rules.starts_comment(flag) { ~"-{" + escape(*name, "{}") + "}" }
+      else { flag }
+    }
+    Argument(ref term) => format_term(term, indent, rules)
+  }
+}
+
+pub fn format_term(term: &Term, indent: uint, rules: &FormatRules) -> ~str {
+  match *term {
+    Block(commands) => format_commands("[", commands, "]", indent, rules),
+    Subst(commands) => format_commands("(", commands, ")", indent, rules),
+    Variable(ref name) => format_variable(*name, None),
+    String(ref s) => {
+      if is_plain_word(*s, rules) { ~"'" + *s }
+      else { ~"'{" + escape(*s, "{}") + "}" }
+    }
+    Interp(pieces) => {
+      match format_bareword(pieces, indent, rules) {
+        Some(word) => word,
+        None => ~"\"{" + format_interp_pieces(pieces, indent, rules) + "}"
+      }
+    }
+    NumberLiteral(n) => fmt!("%u", n as uint),
+  }
+}
+
+// a block or substitution: inline if it's a single short command,
+// otherwise one command per line, indented.
+fn format_commands(open: &str, commands: &[@Command], close: &str,
+                   indent: uint, rules: &FormatRules) -> ~str {
+  let commands = vec::filter(commands, |c| !is_empty(*c));
+
+  if commands.len() == 0u {
+    return open.to_owned() + close;
+  }
+
+  if commands.len() == 1u {
+    let inner = format_command(commands[0], indent, rules);
+    if !inner.contains_char('\n') {
+      return open.to_owned() + inner + close;
+    }
+  }
+
+  let mut out = open.to_owned() + "\n";
+
+  for commands.each |command| {
+    out += indentation(indent + 1u);
+    out += format_command(*command, indent + 1u, rules);
+    out += "\n";
+  }
+
+  out + indentation(indent) + close
+}
+
+// `$name` where that reads back the same, or `${name}`.  `next` is the
+// piece of an interpolation that follows the variable, if any.
+fn format_variable(name: &str, next: Option<&Term>) -> ~str {
+  let ambiguous = match next {
+    Some(term) => match *term {
+      String(ref s) if s.len() > 0u => {
+        // the first character, not byte: `s` may not be ascii
+        let ch = str::char_at(*s, 0u);
+        is_varname_char(ch) || ch == '{' || ch == '('
+      }
+      _ => false
+    },
+    None => false
+  };
+
+  if !ambiguous && str::all(name, is_varname_char) {
+    ~"$" + name
+  }
+  else {
+    ~"${" + escape(name, "{}") + "}"
+  }
+}
+
+// the pieces of an interpolation as a bareword, if they can be
+// written as one
+fn format_bareword(pieces: &[Term], indent: uint,
+                   rules: &FormatRules) -> Option<~str> {
+  // a word has to start with plain text or a `$(...)`: one starting
+  // with any other `$` is a variable on its own, and the rest is a
+  // separate word.  a lone all-digit string would read back as a
+  // number.
+  if pieces.is_empty() { return None; }
+
+  match pieces[0] {
+    String(ref s) if s.len() > 0u &&
+                     is_bareword_start(str::char_at(*s, 0u)) => {
+      if pieces.len() == 1u && number_value(*s).is_some() { return None; }
+    }
+    Subst(_) => {}
+    _ => { return None; }
+  }
+
+  let mut out = ~"";
+
+  for pieces.eachi |i, piece| {
+    match *piece {
+      String(ref s) => {
+        if !str::all(*s, |ch| is_bareword_char(ch, rules)) { return None; }
+        out += *s;
+      }
+      _ => { out += format_interp_piece(pieces, i, indent, rules); }
+    }
+  }
+
+  if rules.starts_comment(out) { None } else { Some(out) }
+}
+
+// the inside of a "{...} string
+fn format_interp_pieces(pieces: &[Term], indent: uint,
+                        rules: &FormatRules) -> ~str {
+  let mut out = ~"";
+
+  for pieces.eachi |i, piece| {
+    match *piece {
+      String(ref s) => { out += escape(*s, "${}"); }
+      _ => { out += format_interp_piece(pieces, i, indent, rules); }
+    }
+  }
+
+  out
+}
+
+fn format_interp_piece(pieces: &[Term], i: uint, indent: uint,
+                       rules: &FormatRules) -> ~str {
+  let next = if i + 1u < pieces.len() { Some(&pieces[i + 1u]) } else { None };
+
+  match pieces[i] {
+    Variable(ref name) => format_variable(*name, next),
+    Subst(commands) => {
+      ~"$" + format_commands("(", commands, ")", indent, rules)
+    }
+    ref other => fail fmt!("ixl: can't interpolate %?", other)
+  }
+}
+
+// whether `s` can be written after a `'` or `-` without braces
+fn is_plain_word(s: &str, rules: &FormatRules) -> bool {
+  s.len() > 0u && s[0] != '{' as u8 && str::all(s, |ch| {
+    !rules.ends_word(ch) && ch != '\\'
+  })
+}
+
+fn is_bareword_char(ch: char, rules: &FormatRules) -> bool {
+  !rules.ends_word(ch) && !"$\\".contains_char(ch)
+}
+
+// barewords can't start with anything parse_term or parse_command
+// would treat specially
+pure fn is_bareword_start(ch: char) -> bool {
+  !"'\"[($@%-".contains_char(ch)
+}
+
+pure fn is_varname_char(ch: char) -> bool {
+  char::is_alphanumeric(ch) || "-_".contains_char(ch)
+}
+
+fn is_empty(command: &Command) -> bool {
+  command.target.is_none() && command.components.len() == 0u &&
+    command.pipe.is_none()
+}
+
+// backslash-escapes backslashes, and the characters in `specials`
+fn escape(s: &str, specials: &str) -> ~str {
+  do io::with_str_writer |out| {
+    for str::each_char(s) |ch| {
+      if ch == '\\' || specials.contains_char(ch) { out.write_char('\\'); }
+      out.write_char(ch);
+    }
+  }
+}
+
+fn indentation(indent: uint) -> ~str {
+  str::repeat("  ", indent)
+}
+
+#[cfg(test)]
+fn check(source: &str, expected: &str) {
+  use ixl::parser::parse_str;
+
+  let formatted = format_program(&parse_str(source));
+
+  if formatted != expected.to_owned() {
+    fail fmt!("formatted [%s] as [%s], expected [%s]",
+              source, formatted, expected);
+  }
+
+  // the formatted source reads back as the same program, and
+  // formatting it again changes nothing
+  assert(parse_str(formatted) == parse_str(source));
+  assert(format_program(&parse_str(formatted)) == formatted);
+}
+
+#[test]
+fn test_format_commands() {
+  check("foo   -a --why 'bar\n\n\nbaz", "foo -a --why 'bar\nbaz\n");
+  check("echo 'hello |\n  tr a-z A-Z;ls", "echo 'hello | tr a-z A-Z\nls\n");
+  check("# comment\nfoo # trailing\n;;", "foo\n");
+  check("@'out cat -{a b} --", "@'out cat --{a b} --{}\n");
+  check("%ixl 2\nfoo", "%ixl 2\nfoo\n");
+}
+
+#[test]
+fn test_format_blocks() {
+  check("if [foo] [bar; baz]", "if [foo] [\n  bar\n  baz\n]\n");
+  check("a [b [c; d]] []", "a [\n  b [\n    c\n    d\n  ]\n] []\n");
+  check("echo ( date ) x$( date )", "echo (date) x$(date)\n");
+  check("echo $( date ) $(date).log", "echo $(date) $(date).log\n");
+}
+
+#[test]
+fn test_format_terms() {
+  check("echo foo/$bar.txt ${a b} x${c}d $",
+        "echo foo/$bar.txt ${a b} x${c}d $\n");
+  check("echo \"{a b ${c}d} \"{x\\$y}", "echo \"{a b ${c}d} \"{x\\$y}\n");
+  check("echo 007 42 {x} \\-x \\'q", "echo 007 42 {x} \"{-x} \"{'q}\n");
+  check("echo '{a b} '{} \"{} '{\\{}", "echo '{a b} '{} \"{} '{\\{}\n");
+  check("echo \"{42} a\\tb", "echo \"{42} \"{a\tb}\n");
+  check("echo \"{$x.txt}", "echo \"{$x.txt}\n");
+  check("echo \"{${x}\u05d0} \"{$x\u00a0}",
+        "echo \"{${x}\u05d0} \"{$x\u00a0}\n");
+  check("echo \"{${x}-suffix}", "echo \"{${x}-suffix}\n");
+  check("echo \"{$(date).log}", "echo $(date).log\n");
+}
+
+// keeps `inline`'s block arguments on one line, but leaves commands
+// with only one argument to the formatter
+#[cfg(test)]
+extern fn inline_rule(command: &Command, indent: uint, rules: &FormatRules)
+    -> Option<~str> {
+  if command.components.len() < 2u { return None; }
+
+  let words = do command.components.map |component| {
+    match **component {
+      Argument(Block(commands)) => {
+        let inner = commands.map(|c| format_words(*c, indent, rules));
+        ~"[" + str::connect(inner, "; ") + "]"
+      }
+      Argument(ref term) => format_term(term, indent, rules),
+      Flag(_) => format_component(*component, indent, rules),
+    }
+  };
+
+  Some(str::connect(words, " "))
+}
+
+#[test]
+fn test_format_rules() {
+  use ixl::parser::parse_str;
+
+  let rules = FormatRules();
+  rules.register("inline", inline_rule);
+
+  let format = |source: &str| format_program_with(&parse_str(source), &rules);
+
+  assert(format("inline  [a; b]   [c] | x [d; e]") ==
+         ~"inline [a; b] [c] | x [\n  d\n  e\n]\n");
+  assert(format("y [inline [a; b]]") == ~"y [inline [a; b]]\n");
+  assert(format("inline") == ~"inline\n");
+  assert(format("'inline [a; b]") == ~"'inline [a; b]\n");
+  assert(format("other [a; b]") == ~"other [\n  a\n  b\n]\n");
+}
+
+#[test]
+fn test_format_comment_syntax() {
+  use ixl::parser::scanner_with_options;
+
+  let options = ParserOptions {
+    line_comments: ~[~"//", ~"--"],
+    block_comments: Some((~"/*", ~"*/")),
+    .. default_options()
+  };
+
+  let rules = format_rules_with_options(copy options);
+  let parse = |source: &str| {
+    io::with_str_reader(source, |r| {
+      scanner_with_options(r, copy options).parse()
+    })
+  };
+
+  let source = "echo a#b \"{//x} \"{/*y} -{why} 'c#d";
+  let formatted = format_program_with(&parse(source), &rules);
+  assert(formatted == ~"echo a#b \"{//x} \"{/*y} -{why} 'c#d\n");
+  assert(parse(formatted) == parse(source));
+
+  // the same program under the default syntax
+  assert(format_program(&parse(source)) ==
+         ~"echo \"{a#b} //x /*y --why '{c#d}\n");
+}
+
+#[test]
+fn test_format_source() {
+  let format = |source: &str| {
+    io::with_str_reader(source, |r| format_source(r, &FormatRules()))
+  };
+
+  assert(format("%ixl 2\nfoo   bar") == Ok(~"%ixl 2\nfoo bar\n"));
+
+  let v2 = format_rules_with_options(ParserOptions {
+    version: 2u,
+    .. default_options()
+  });
+
+  let format_v2 = |source: &str| {
+    io::with_str_reader(source, |r| format_source(r, &v2))
+  };
+
+  assert(format_v2("%ixl 1\nfoo |\nbar") == Ok(~"%ixl 1\nfoo | bar\n"));
+  assert(format_v2("%ixl 2\nfoo") == Ok(~"foo\n"));
+  assert(format_v2("foo") == Ok(~"foo\n"));
+
+  assert(format("foo '{a#b}  \"{#c}") == Ok(~"foo '{a#b} \"{#c}\n"));
+
+  assert(format("foo # note").is_err());
+  assert(format("foo #* note *# bar").is_err());
+  assert(format("%if windows\nfoo\n%end").is_err());
+  assert(format("%if windows\nfoo\n%else\nbar\n%end").is_err());
+}
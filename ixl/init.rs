@@ -0,0 +1,39 @@
+/**
+ * The user's init file
+ *
+ * Lives at `$XDG_CONFIG_HOME/ixl/init.ixl`, where XDG_CONFIG_HOME
+ * defaults to `$HOME/.config` when it's unset or empty (or, per the
+ * spec, not an absolute path).
+ */
+pub fn init_file() -> Option<Path> {
+  init_file_from(os::getenv("XDG_CONFIG_HOME"), os::getenv("HOME"))
+}
+
+pub fn init_file_from(xdg_config_home: Option<~str>, home: Option<~str>)
+    -> Option<Path> {
+  let config = match xdg_config_home {
+    Some(dir) if dir.starts_with("/") => Path(dir),
+    _ => match home {
+      Some(dir) if !dir.is_empty() => Path(dir).push(".config"),
+      _ => { return None; }
+    }
+  };
+
+  Some(config.push_many([~"ixl", ~"init.ixl"]))
+}
+
+#[test]
+fn test_init_file_from() {
+  let path = |s: &str| Some(Path(s));
+
+  assert(init_file_from(Some(~"/xdg"), Some(~"/home/me")) ==
+         path("/xdg/ixl/init.ixl"));
+  assert(init_file_from(None, Some(~"/home/me")) ==
+         path("/home/me/.config/ixl/init.ixl"));
+  assert(init_file_from(Some(~""), Some(~"/home/me")) ==
+         path("/home/me/.config/ixl/init.ixl"));
+  assert(init_file_from(Some(~"relative"), Some(~"/home/me")) ==
+         path("/home/me/.config/ixl/init.ixl"));
+  assert(init_file_from(Some(~"/xdg"), None) == path("/xdg/ixl/init.ixl"));
+  assert(init_file_from(None, None) == None);
+}
@@ -0,0 +1,385 @@
+use std::map::HashMap;
+use std::time;
+
+use ixl::env::Env;
+
+use ixl::parser::{Program, Command, Flag, Argument};
+use ixl::parser::{Term, Block, Subst, Variable, String, Interp, NumberLiteral};
+
+/**
+ * Values
+ */
+#[deriving_eq]
+pub enum Value {
+  Nil,
+  Str(~str),
+  Num(u32),
+  List(@[Value]),
+  // a block, left for whatever receives it to run
+  Closure(@[@Command]),
+}
+
+impl Value {
+  // the value as it appears in a string or on output
+  fn to_str(&self) -> ~str {
+    match *self {
+      Nil => ~"",
+      Str(ref s) => copy *s,
+      Num(n) => fmt!("%u", n as uint),
+      List(items) => str::connect(items.map(|v| v.to_str()), " "),
+      Closure(_) => ~"[...]",
+    }
+  }
+}
+
+#[deriving_eq]
+pub enum RuntimeError {
+  UnknownCommand(~str),
+  UnknownVariable(~str),
+  // a command's first argument isn't something that can name one
+  BadCommandName(~str),
+  // a builtin was called with arguments it can't use
+  BadArguments(~str, ~str),
+  // syntax the interpreter doesn't give a meaning to yet
+  Unsupported(~str),
+  // a `timeout` ran out before its block finished
+  TimedOut,
+}
+
+impl RuntimeError {
+  fn to_str(&self) -> ~str {
+    match *self {
+      UnknownCommand(ref name) => fmt!("unknown command `%s`", *name),
+      UnknownVariable(ref name) => fmt!("unknown variable `$%s`", *name),
+      BadCommandName(ref what) => fmt!("%s can't name a command", *what),
+      BadArguments(ref name, ref msg) => fmt!("%s: %s", *name, *msg),
+      Unsupported(ref what) => fmt!("%s aren't supported yet", *what),
+      TimedOut => ~"timed out",
+    }
+  }
+}
+
+/**
+ * Builtins and the command registry
+ */
+pub trait Builtin {
+  fn call(&self, ctx: &mut Context, flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError>;
+}
+
+pub type BuiltinFn = extern fn(ctx: &mut Context, flags: &[~str],
+                               args: &[Value]) -> Result<Value, RuntimeError>;
+
+// a builtin implemented by a plain function
+pub struct NativeBuiltin {
+  f: BuiltinFn,
+}
+
+impl NativeBuiltin : Builtin {
+  fn call(&self, ctx: &mut Context, flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+    (self.f)(ctx, flags, args)
+  }
+}
+
+pub fn native(f: BuiltinFn) -> @Builtin {
+  @NativeBuiltin { f: f } as @Builtin
+}
+
+pub struct CommandRegistry {
+  commands: HashMap<~str, @Builtin>,
+}
+
+pub fn CommandRegistry() -> CommandRegistry {
+  CommandRegistry { commands: HashMap() }
+}
+
+impl CommandRegistry {
+  // registers a command, replacing any existing one by that name
+  fn register(&self, name: &str, builtin: @Builtin) {
+    self.commands.insert(name.to_owned(), builtin);
+  }
+
+  fn find(&self, name: &str) -> Option<@Builtin> {
+    self.commands.find(name.to_owned())
+  }
+}
+
+/**
+ * The interpreter
+ */
+pub struct Context {
+  registry: CommandRegistry,
+  env: Env,
+  out: io::Writer,
+  // the value piped into the current command, if any
+  mut input: Option<Value>,
+  // when set, the time (from precise_time_ns) after which no further
+  // commands are started
+  mut deadline: Option<u64>,
+}
+
+pub fn Context(registry: CommandRegistry, out: io::Writer) -> Context {
+  Context {
+    registry: registry,
+    env: Env(),
+    out: out,
+    input: None,
+    deadline: None,
+  }
+}
+
+impl Context {
+  // runs each command in turn, giving the value of the last one
+  fn eval_program(&mut self, program: &Program)
+    -> Result<Value, RuntimeError> {
+    self.eval_commands(program.commands)
+  }
+
+  fn eval_commands(&mut self, commands: &[@Command])
+    -> Result<Value, RuntimeError> {
+    let mut result = Nil;
+
+    for commands.each |command| {
+      match self.eval_command(*command) {
+        Ok(value) => { result = value; }
+        Err(e) => { return Err(e); }
+      }
+    }
+
+    Ok(result)
+  }
+
+  // runs a command and then any commands piped from it, each getting
+  // the value of the one before as its input
+  fn eval_command(&mut self, command: &Command)
+    -> Result<Value, RuntimeError> {
+    let value = match self.call_command(command) {
+      Ok(value) => value,
+      Err(e) => { return Err(e); }
+    };
+
+    match command.pipe {
+      None => Ok(value),
+      Some(next) => {
+        let mut input = Some(value);
+        self.input <-> input;
+        let result = self.eval_command(next);
+        self.input <-> input;
+        result
+      }
+    }
+  }
+
+  // runs a single command: its first argument names a builtin in the
+  // registry, which is called with its flags and the rest of its
+  // arguments.
+  fn call_command(&mut self, command: &Command)
+    -> Result<Value, RuntimeError> {
+    if self.expired() { return Err(TimedOut); }
+
+    if command.target.is_some() { return Err(Unsupported(~"targets")); }
+
+    let mut flags = ~[];
+    let mut args = ~[];
+
+    for command.components.each |component| {
+      match **component {
+        Flag(ref name) => { flags.push(copy *name); }
+        Argument(ref term) => {
+          match self.eval_term(term) {
+            Ok(value) => { args.push(value); }
+            Err(e) => { return Err(e); }
+          }
+        }
+      }
+    }
+
+    if args.is_empty() { return Ok(Nil); }
+
+    let name = match args[0] {
+      Str(ref name) => copy *name,
+      Num(n) => fmt!("%u", n as uint),
+      Nil => { return Err(BadCommandName(~"an empty value")); }
+      List(_) => { return Err(BadCommandName(~"a list")); }
+      Closure(_) => { return Err(BadCommandName(~"a block")); }
+    };
+
+    match self.registry.find(name) {
+      Some(builtin) => builtin.call(self, flags, vec::tail(args)),
+      None => Err(UnknownCommand(name))
+    }
+  }
+
+  // runs the commands of a block in a new scope
+  fn eval_block(&mut self, commands: &[@Command])
+    -> Result<Value, RuntimeError> {
+    self.env.push_scope();
+    let result = self.eval_commands(commands);
+    self.env.pop_scope();
+    result
+  }
+
+  // runs the commands of a block, giving up with TimedOut if they're
+  // still going after `ns` nanoseconds. an enclosing timeout that
+  // expires sooner still applies.
+  fn eval_with_timeout(&mut self, commands: &[@Command], ns: u64)
+    -> Result<Value, RuntimeError> {
+    let outer = self.deadline;
+    let now = time::precise_time_ns();
+
+    // saturate instead of wrapping around into the past
+    let deadline = if ns > u64::max_value - now { u64::max_value }
+      else { now + ns };
+
+    self.deadline = match outer {
+      Some(d) if d < deadline => Some(d),
+      _ => Some(deadline)
+    };
+
+    let result = self.eval_block(commands);
+    self.deadline = outer;
+    result
+  }
+
+  fn expired(&self) -> bool {
+    match self.deadline {
+      Some(deadline) => time::precise_time_ns() >= deadline,
+      None => false
+    }
+  }
+
+  fn eval_term(&mut self, term: &Term) -> Result<Value, RuntimeError> {
+    match *term {
+      Block(commands) => Ok(Closure(commands)),
+      Subst(commands) => self.eval_commands(commands),
+      // a bare `$` is the piped input
+      Variable(ref name) if name.is_empty() => match self.input {
+        Some(ref value) => Ok(copy *value),
+        None => Ok(Nil)
+      },
+      Variable(ref name) => match self.env.get(*name) {
+        Some(value) => Ok(value),
+        None => Err(UnknownVariable(copy *name))
+      },
+      String(ref s) => Ok(Str(copy *s)),
+      NumberLiteral(n) => Ok(Num(n)),
+      Interp(pieces) => {
+        // a lone piece keeps its value, so `$(list ...)` is a list
+        if pieces.len() == 1u { return self.eval_term(&pieces[0]); }
+
+        let mut s = ~"";
+
+        for pieces.each |piece| {
+          match self.eval_term(piece) {
+            Ok(value) => { s += value.to_str(); }
+            Err(e) => { return Err(e); }
+          }
+        }
+
+        Ok(Str(s))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+pub fn run(source: &str, registry: CommandRegistry)
+    -> (Result<Value, RuntimeError>, ~str) {
+  use ixl::parser::parse_str;
+
+  let mut result = Ok(Nil);
+
+  let output = do io::with_str_writer |out| {
+    let mut ctx = Context(registry, out);
+    result = ctx.eval_program(&parse_str(source));
+  };
+
+  (result, output)
+}
+
+#[cfg(test)]
+extern fn shout(ctx: &mut Context, _flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  for args.each |arg| {
+    ctx.out.write_str(str::to_upper(arg.to_str()));
+  }
+
+  Ok(Num(args.len() as u32))
+}
+
+#[cfg(test)]
+extern fn list(_ctx: &mut Context, _flags: &[~str], args: &[Value])
+    -> Result<Value, RuntimeError> {
+  Ok(List(at_vec::from_slice(args)))
+}
+
+#[test]
+fn test_eval_commands() {
+  let registry = CommandRegistry();
+  registry.register("shout", native(shout));
+  registry.register("list", native(list));
+
+  let (result, output) = run("shout a 'b; shout \"{c$(shout d)}", registry);
+  assert(result == Ok(Num(1)));
+  assert(output == ~"ABDC1");
+
+  let (result, _) = run("list 1 [x] $(list a b)", registry);
+  assert(result == Ok(List(@[
+    Num(1),
+    Closure(@[@Command {
+      target: None, pipe: None,
+      components: @[@Argument(Interp(@[String(~"x")]))]
+    }]),
+    List(@[Str(~"a"), Str(~"b")]),
+  ])));
+
+  let (result, _) = run("", registry);
+  assert(result == Ok(Nil));
+}
+
+#[test]
+fn test_eval_errors() {
+  let registry = CommandRegistry();
+  registry.register("shout", native(shout));
+
+  let (result, output) = run("shout a\nnope b\nshout c", registry);
+  assert(result == Err(UnknownCommand(~"nope")));
+  assert(output == ~"A");
+
+  let (result, _) = run("[shout]", registry);
+  assert(result == Err(BadCommandName(~"a block")));
+
+  let (result, _) = run("shout $x", registry);
+  assert(result == Err(UnknownVariable(~"x")));
+}
+
+#[test]
+fn test_register_override() {
+  let registry = CommandRegistry();
+  registry.register("x", native(shout));
+  registry.register("x", native(list));
+
+  let (result, _) = run("x a", registry);
+  assert(result == Ok(List(@[Str(~"a")])));
+}
+
+#[test]
+fn test_eval_pipes() {
+  let registry = CommandRegistry();
+  registry.register("shout", native(shout));
+  registry.register("list", native(list));
+
+  let (result, output) = run("list a b | list $ c | shout $", registry);
+  assert(result == Ok(Num(1)));
+  assert(output == ~"A B C");
+
+  // input only lasts for the piped command
+  let (result, _) = run("list a | list $; list $", registry);
+  assert(result == Ok(List(@[Nil])));
+
+  // `$(...)` at the start of a word is a substitution, not `$` (the
+  // input) followed by a separate argument
+  let (result, _) = run("list a | list $(list b c)", registry);
+  assert(result == Ok(List(@[List(@[Str(~"b"), Str(~"c")])])));
+}
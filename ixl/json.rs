@@ -0,0 +1,122 @@
+use std::json;
+use std::json::ToJson;
+use core::send_map::linear::LinearMap;
+
+use ixl::parser::{Program, Command, Component, Flag, Argument};
+use ixl::parser::{Term, Block, Subst, Variable, String, Interp, NumberLiteral};
+
+/**
+ * JSON output of the AST
+ *
+ * Every node is an object with a "type" field naming it, so tools in
+ * other languages can walk parse output from `ixl json <file>`.
+ */
+impl Program : ToJson {
+  fn to_json() -> json::Json {
+    node("program", ~[
+      (~"version", (self.version as float).to_json()),
+      (~"commands", commands_json(self.commands)),
+    ])
+  }
+}
+
+impl Command : ToJson {
+  fn to_json() -> json::Json {
+    node("command", ~[
+      (~"target", match self.target {
+        Some(ref target) => target.to_json(),
+        None => json::Null
+      }),
+      (~"components", json::List(self.components.map(|c| c.to_json()))),
+      (~"pipe", match self.pipe {
+        Some(pipe) => pipe.to_json(),
+        None => json::Null
+      }),
+    ])
+  }
+}
+
+impl Component : ToJson {
+  fn to_json() -> json::Json {
+    match self {
+      Flag(ref name) => node("flag", ~[(~"name", name.to_json())]),
+      Argument(ref term) => node("argument", ~[(~"term", term.to_json())]),
+    }
+  }
+}
+
+impl Term : ToJson {
+  fn to_json() -> json::Json {
+    match self {
+      Block(commands) => node("block", ~[
+        (~"commands", commands_json(commands))
+      ]),
+      Subst(commands) => node("subst", ~[
+        (~"commands", commands_json(commands))
+      ]),
+      Variable(ref name) => node("variable", ~[(~"name", name.to_json())]),
+      String(ref value) => node("string", ~[(~"value", value.to_json())]),
+      Interp(pieces) => node("interp", ~[
+        (~"pieces", json::List(pieces.map(|p| p.to_json())))
+      ]),
+      NumberLiteral(n) => node("number", ~[
+        (~"value", (n as float).to_json())
+      ]),
+    }
+  }
+}
+
+fn commands_json(commands: &[@Command]) -> json::Json {
+  json::List(commands.map(|c| c.to_json()))
+}
+
+// an object for an AST node of the given type
+fn node(type_name: &str, fields: ~[(~str, json::Json)]) -> json::Json {
+  let mut object = LinearMap();
+  object.insert(~"type", json::String(type_name.to_owned()));
+
+  do vec::consume(fields) |_, (key, value)| {
+    object.insert(key, value);
+  }
+
+  json::Object(~object)
+}
+
+#[test]
+fn test_json() {
+  use ixl::parser::parse_str;
+
+  let program = parse_str("@'out echo -n \"{a $b} 3 | [x]");
+  let expected = result::unwrap(json::from_str(~"
+    {\"type\": \"program\", \"version\": 1, \"commands\": [
+      {\"type\": \"command\",
+       \"target\": {\"type\": \"string\", \"value\": \"out\"},
+       \"components\": [
+         {\"type\": \"argument\", \"term\":
+           {\"type\": \"interp\", \"pieces\": [
+             {\"type\": \"string\", \"value\": \"echo\"}]}},
+         {\"type\": \"flag\", \"name\": \"n\"},
+         {\"type\": \"argument\", \"term\":
+           {\"type\": \"interp\", \"pieces\": [
+             {\"type\": \"string\", \"value\": \"a \"},
+             {\"type\": \"variable\", \"name\": \"b\"}]}},
+         {\"type\": \"argument\", \"term\":
+           {\"type\": \"number\", \"value\": 3}}
+       ],
+       \"pipe\": {\"type\": \"command\", \"target\": null, \"pipe\": null,
+         \"components\": [
+           {\"type\": \"argument\", \"term\": {\"type\": \"block\",
+             \"commands\": [
+               {\"type\": \"command\", \"target\": null, \"pipe\": null,
+                \"components\": [
+                  {\"type\": \"argument\", \"term\":
+                    {\"type\": \"interp\", \"pieces\": [
+                      {\"type\": \"string\", \"value\": \"x\"}]}}
+                ]}
+             ]}}
+         ]}}
+    ]}
+  "));
+
+  assert(program.to_json() == expected);
+}
@@ -6,27 +6,244 @@ use either::{Left,Right,Either};
 /**
  * The AST
  */
+#[deriving_eq]
 pub enum Term {
   Block(@[@Command]),
   Subst(@[@Command]),
   Variable(~str),
   String(~str),
   Interp(@[Term]),
+  NumberLiteral(u32),
 }
 
+#[deriving_eq]
 pub enum Component {
   Flag(~str),
   Argument(Term)
 }
 
+#[deriving_eq]
 pub struct Command {
   target: Option<Term>,
   components: @[@Component],
   pipe: Option<@Command>
 }
 
+#[deriving_eq]
 pub struct Program {
-  commands: @[@Command]
+  commands: @[@Command],
+  // the language version the program was parsed as
+  version: uint,
+}
+
+/**
+ * Fragments, for parsing the inside of a construct on its own
+ */
+pub enum Ambient {
+  // the inside of a `[...]` block
+  InBlock,
+  // the inside of a `(...)` or `$(...)` substitution
+  InSubst,
+  // the inside of a `"{...}` interpolated string
+  InInterp,
+}
+
+pub enum Fragment {
+  Commands(@[@Command]),
+  Pieces(@[Term]),
+}
+
+/**
+ * Parser options
+ */
+pub const LATEST_VERSION: uint = 2u;
+
+pub struct ParserOptions {
+  // the language version to parse as when a script doesn't start with
+  // an `%ixl <version>` pragma.  the pragma replaces this, and the
+  // options that depend on it, with the ones from dialect_options.
+  // versions after 1 do the same here: the scanner takes their
+  // pipes_across_newlines and empty_commands from dialect_options.
+  // version 1 leaves them as given, so they can be customized.
+  version: uint,
+
+  // whether a `|` at the start of a line continues the pipeline of
+  // the command before it.  when this is off, a pipeline can only
+  // cross a newline with a trailing `|` or a `\`.  when it's on, each
+  // `|` that does this gets a warning.
+  pipes_across_newlines: bool,
+
+  // what to do with a `;` that has no command before it, as in `;;`
+  // or `foo ;; bar`.  blank lines and comments are never commands.
+  empty_commands: EmptyCommands,
+
+  // names (and values) that `%if` directives are checked against
+  defines: ~[(~str, ~str)],
+
+  // whether branches stripped by `%if` must still parse.  when this
+  // is off they are skipped line by line, looking only for the
+  // directives that end them (even inside multi-line strings and
+  // block comments).
+  check_stripped: bool,
+
+  // strings that start a comment running to the end of the line.
+  // each is one or two ascii characters.  a one-character introducer
+  // also ends any word it appears in, the way `#` does; a longer one
+  // starts a comment at the beginning of any word.  avoid ones that
+  // start with `-`: with `--`, `foo --bar` is `foo` and a comment, and
+  // the flag has to be written `-{bar}`.
+  line_comments: ~[~str],
+
+  // the opening and closing delimiters of block comments, if any,
+  // with the same length rules as line_comments.  block comments
+  // nest, so they can comment out code that has comments in it.
+  // version 2 has `#* ... *#` ones unless others are given.
+  block_comments: Option<(~str, ~str)>,
+}
+
+pub enum EmptyCommands {
+  // drop them (the default)
+  SkipEmpty,
+  // produce a command with no target, components or pipe
+  KeepEmpty,
+  // fail with a parse error
+  RejectEmpty,
+}
+
+// checks options that can't be right whatever the input, so that
+// scanner_with_options doesn't have to fail
+pub fn validate_options(options: &ParserOptions) -> Result<(), ~str> {
+  if options.version < 1u || options.version > LATEST_VERSION {
+    return Err(fmt!("unknown language version %u (this parser knows \
+                     versions 1 to %u)", options.version, LATEST_VERSION));
+  }
+
+  let mut delimiters = copy options.line_comments;
+
+  match options.block_comments {
+    Some((ref open, ref close)) => {
+      delimiters.push(copy *open);
+      delimiters.push(copy *close);
+    }
+    None => {}
+  }
+
+  for delimiters.each |delimiter| {
+    let ok = (delimiter.len() == 1u || delimiter.len() == 2u) &&
+      str::all(*delimiter, |ch| {
+        char::is_ascii(ch) && !is_linespace(ch) && ch != ';'
+      });
+
+    if !ok {
+      return Err(fmt!("bad comment delimiter `%s`: delimiters are one or \
+                       two ascii characters, without spaces or `;`",
+                      *delimiter));
+    }
+  }
+
+  Ok(())
+}
+
+pub fn default_options() -> ParserOptions {
+  ParserOptions {
+    version: 1u,
+    pipes_across_newlines: true,
+    empty_commands: SkipEmpty,
+    defines: ~[],
+    check_stripped: false,
+    line_comments: ~[~"#"],
+    block_comments: None,
+  }
+}
+
+// `base`, with the dialect options the given language version sets:
+//
+//   1: the original grammar.
+//   2: pipelines only continue across newlines with a trailing `|` or
+//      a `\`, stray semicolons are errors, and `#* ... *#` are block
+//      comments (unless `base` has block comments of its own).
+pub fn dialect_options(version: uint, base: ParserOptions)
+    -> Option<ParserOptions> {
+  let default_block = Some((~"#*", ~"*#"));
+
+  match version {
+    1u => Some(ParserOptions {
+      version: 1u,
+      pipes_across_newlines: true,
+      empty_commands: SkipEmpty,
+      // in version 1, `#*****` is a line comment like any other
+      block_comments: if base.block_comments == default_block { None }
+        else { copy base.block_comments },
+      .. base
+    }),
+    2u => Some(ParserOptions {
+      version: 2u,
+      pipes_across_newlines: false,
+      empty_commands: RejectEmpty,
+      block_comments: if base.block_comments.is_none() { default_block }
+        else { copy base.block_comments },
+      .. base
+    }),
+    _ => None
+  }
+}
+
+/**
+ * Source positions
+ */
+#[deriving_eq]
+pub struct Position {
+  // offset in bytes of utf-8 input
+  byte: uint,
+  // 1-based line number
+  line: uint,
+  // 1-based column, counted in characters
+  col: uint,
+  // 1-based column, counted in utf-16 code units, as LSP clients do
+  utf16_col: uint,
+}
+
+pub fn start_position() -> Position {
+  Position { byte: 0u, line: 1u, col: 1u, utf16_col: 1u }
+}
+
+impl Position {
+  // the position just after `ch`, if `ch` is at this position
+  pure fn advance(&self, ch: char) -> Position {
+    let byte = self.byte + utf8_len(ch);
+
+    if ch == '\n' {
+      Position { byte: byte, line: self.line + 1u, col: 1u, utf16_col: 1u }
+    }
+    else {
+      Position {
+        byte: byte,
+        line: self.line,
+        col: self.col + 1u,
+        utf16_col: self.utf16_col + utf16_len(ch),
+      }
+    }
+  }
+}
+
+pure fn utf8_len(ch: char) -> uint {
+  let code = ch as uint;
+  if code < 0x80u { 1u }
+  else if code < 0x800u { 2u }
+  else if code < 0x10000u { 3u }
+  else { 4u }
+}
+
+pure fn utf16_len(ch: char) -> uint {
+  if (ch as uint) < 0x10000u { 1u } else { 2u }
+}
+
+/**
+ * Warnings, for things that parse but may not mean what they look like
+ */
+pub struct Warning {
+  pos: Position,
+  msg: ~str,
 }
 
 /**
@@ -34,47 +251,91 @@ pub struct Program {
  */
 pub struct Scanner {
   reader: io::Reader,
+  mut options: ParserOptions,
   mut cursor: char,
   mut lookahead: Option<char>,
-  mut line: uint,
-  mut col: uint,
+  // the position of the cursor
+  mut pos: Position,
+
+  // whether anything but comments and blank lines has been read,
+  // after which an `%ixl` pragma is too late
+  mut past_header: bool,
+
+  mut warnings: ~[Warning],
+
+  // whether the input had anything the AST doesn't keep, which the
+  // formatter would lose
+  mut saw_comment: bool,
+  mut saw_conditional: bool,
+
+  // bookkeeping for ParseMetrics
+  mut depth: uint,
+  mut max_depth: uint,
+  mut command_count: uint,
+}
+
+/**
+ * Parse metrics, for embedders keeping an eye on their scripts
+ */
+pub struct ParseMetrics {
+  // bytes of input consumed
+  bytes: uint,
+  // commands parsed, counting each stage of a pipeline and each
+  // command inside a block or substitution, but not commands in
+  // branches stripped by `%if`
+  commands: uint,
+  // the deepest nesting of blocks and substitutions
+  max_depth: uint,
+  // wall-clock time spent parsing
+  duration_ns: u64,
 }
 
 pub fn Scanner(reader: io::Reader) -> Scanner {
-  let s = Scanner {
-    reader: reader,
-    cursor: 0 as char,
-    lookahead: None,
-    line: 1u, col: 0u,
-  };
+  scanner_with_options(reader, default_options())
+}
 
-  s.bump();
+pub fn scanner_with_options(reader: io::Reader,
+                            options: ParserOptions) -> Scanner {
+  match validate_options(&options) {
+    Ok(()) => {}
+    Err(msg) => { fail fmt!("ixl: bad parser options: %s", msg); }
+  }
+
+  let options = if options.version == 1u { options }
+    else { dialect_options(options.version, options).get() };
 
-  s
+  Scanner {
+    reader: reader,
+    options: options,
+    cursor: reader.read_char(),
+    lookahead: None,
+    pos: start_position(),
+    past_header: false,
+    warnings: ~[],
+    saw_comment: false,
+    saw_conditional: false,
+    depth: 0u, max_depth: 0u, command_count: 0u,
+  }
 }
 
 impl Scanner {
   fn eof(&self) -> bool { self.cursor == -1 as char }
 
+  fn position(&self) -> Position { self.pos }
+
   fn bump(&self) {
     assert(!self.eof());
 
     let mut lookahead = None;
     lookahead <-> self.lookahead;
 
+    self.pos = self.pos.advance(self.cursor);
+
     self.cursor = match lookahead {
       Some(ch) => { ch }
       None => { self.reader.read_char() }
     };
 
-    if self.cursor == '\n' {
-      self.line += 1u;
-      self.col = 1u;
-    }
-    else {
-      self.col += 1u;
-    }
-
     // io::println(fmt!("bump! cursor: [%c]", self.cursor));
   }
 
@@ -101,72 +362,431 @@ impl Scanner {
   fn consume_escaped(&self, pred: pure fn(char) -> bool) -> ~str {
     do io::with_str_writer |out| {
       while !self.eof() && pred(self.cursor) {
-        // TODO: \uXXXX sequences etc.
-        // this just takes the literal char after the escape
-        // and goes with it.
-        if self.cursor == '\\' {
-          self.bump();
-          if self.eof() { self.error("unterminated escape sequence"); }
-        }
+        if self.cursor == '\\' { out.write_char(self.parse_escape()); }
+        else { out.write_char(self.cursor); }
 
-        out.write_char(self.cursor);
         self.bump();
       };
     }
   }
 
+  // reads the escape sequence starting at the backslash under the
+  // cursor, and returns the character it stands for.  the cursor is
+  // left on the last character of the sequence.
+  //
+  // letters and digits are reserved for named escapes; any other
+  // character escapes to itself.
+  fn parse_escape(&self) -> char {
+    self.bump(); // skip the backslash
+    if self.eof() { self.error("unterminated escape sequence"); }
+
+    match self.cursor {
+      'n' => '\n',
+      't' => '\t',
+      'r' => '\r',
+      'u' => self.parse_unicode_escape(),
+      ch if char::is_alphanumeric(ch) => {
+        self.error(fmt!("unknown escape sequence `\\%s`",
+                        str::from_char(ch)))
+      }
+      ch => ch
+    }
+  }
+
+  // \u{XXXX}, with one to six hex digits
+  fn parse_unicode_escape(&self) -> char {
+    self.bump(); // skip the u
+    if self.cursor != '{' { self.error("expected `{` after `\\u`"); }
+    self.bump();
+
+    let digits = self.consume(is_hex_digit);
+    if self.cursor != '}' { self.error("expected `}` to end `\\u{...}`"); }
+
+    if digits.len() < 1u || digits.len() > 6u {
+      self.error(fmt!("malformed escape `\\u{%s}`", digits));
+    }
+
+    let code = match uint::from_str_radix(digits, 16u) {
+      Some(code) => code,
+      None => self.error(fmt!("malformed escape `\\u{%s}`", digits))
+    };
+
+    if code > 0x10ffffu || (code >= 0xd800u && code <= 0xdfffu) {
+      self.error(fmt!("`\\u{%s}` is not a unicode scalar value", digits));
+    }
+
+    code as char
+  }
+
+  // a marker for the current cursor position, for detecting rules
+  // that return without consuming anything.
+  fn mark(&self) -> uint { self.pos.byte }
+
+  // fails with a parse error, instead of spinning forever, when
+  // nothing has been consumed since `mark` was taken.
+  fn expect_progress(&self, mark: uint) {
+    if self.mark() != mark {
+      return;
+    }
+
+    if self.eof() { self.error("unexpected eof"); }
+    self.error(fmt!("unexpected `%s`", str::from_char(self.cursor)));
+  }
+
   fn error(msg: &str) -> ! {
-    fail fmt!("ixl: parse error at line %u:%u: %s", self.line, self.col, msg);
+    fail fmt!("ixl: parse error at line %u:%u: %s",
+              self.pos.line, self.pos.col, msg);
+  }
+
+  fn warn(&self, msg: &str) {
+    self.warnings.push(Warning { pos: self.pos, msg: msg.to_owned() });
   }
 
   fn parse_spaces(&self) {
     self.consume(is_space);
 
-    while(self.cursor == '\\' && self.peek() == '\n') {
-      self.bump(); self.bump();
+    loop {
+      if self.cursor == '\\' && self.peek() == '\n' {
+        self.bump(); self.bump();
+      }
+      else if self.at_block_comment() {
+        self.skip_comment();
+      }
+      else {
+        break;
+      }
+
       self.consume(is_space);
     }
   }
 
+  // like parse_termspaces, but stops at semicolons, so that a
+  // pipeline can't continue past the end of a command.
+  fn parse_linespaces(&self) {
+    self.consume(is_linespace);
+
+    while self.at_comment() {
+      self.skip_comment();
+      self.consume(is_linespace);
+    }
+  }
+
+  pure fn ends_word(&self, ch: char) -> bool {
+    is_word_terminator(ch) || vec::any(self.options.line_comments, |c| {
+      c.len() == 1u && c[0] as char == ch
+    })
+  }
+
+  fn at_comment(&self) -> bool {
+    self.at_block_comment() || vec::any(self.options.line_comments, |c| {
+      self.at_str(*c)
+    })
+  }
+
+  fn at_block_comment(&self) -> bool {
+    match self.options.block_comments {
+      Some((ref open, _)) => self.at_str(*open),
+      None => false
+    }
+  }
+
+  // whether the input at the cursor starts with a comment delimiter,
+  // which validate_options has made sure is one or two characters
+  fn at_str(&self, s: &str) -> bool {
+    self.cursor == s[0] as char &&
+      (s.len() == 1u || self.peek() == s[1] as char)
+  }
+
+  fn skip_str(&self, s: &str) {
+    for s.len().times { self.bump(); }
+  }
+
+  // skips the comment at the cursor
+  fn skip_comment(&self) {
+    self.saw_comment = true;
+
+    match copy self.options.block_comments {
+      Some((open, close)) if self.at_str(open) => {
+        self.skip_str(open);
+
+        let mut depth = 1u;
+
+        while depth > 0u {
+          if self.eof() { self.error("unterminated block comment"); }
+
+          if self.at_str(close) {
+            self.skip_str(close);
+            depth -= 1u;
+          }
+          else if self.at_str(open) {
+            self.skip_str(open);
+            depth += 1u;
+          }
+          else {
+            self.bump();
+          }
+        }
+      }
+      _ => { self.consume(|x| x != '\n'); }
+    }
+  }
+
   fn parse_block(&self) -> Term {
     if self.cursor != '[' { self.error("expected a block"); }
     self.bump();
-    Block(self.parse_commands_until(']'))
+    Block(self.nested(|| self.parse_commands_until(']')))
   }
 
   fn parse_subst(&self) -> Term {
     if self.cursor != '(' { self.error("expected a block"); }
     self.bump();
-    Subst(self.parse_commands_until(')'))
+    Subst(self.nested(|| self.parse_commands_until(')')))
+  }
+
+  fn nested<T>(&self, f: fn() -> T) -> T {
+    self.depth += 1u;
+    if self.depth > self.max_depth { self.max_depth = self.depth; }
+
+    let result = f();
+
+    self.depth -= 1u;
+    result
   }
 
   fn parse_commands_until(&self, end: char) -> @[@Command] {
+    self.parse_command_list(Some(end))
+  }
+
+  // parses `;`- and newline-separated commands up to `end` (which is
+  // consumed), or up to eof if there is no `end`.
+  fn parse_command_list(&self, end: Option<char>) -> @[@Command] {
     do at_vec::build |push| {
-      while !self.eof() {
-        self.parse_termspaces();
-        if self.cursor == end {
+      // whether a command has been seen since the last `;`
+      let mut filled = false;
+
+      // for each %if we're inside of, innermost last, whether the
+      // branch we're in is taken
+      let mut conds: ~[bool] = ~[];
+
+      loop {
+        self.parse_linespaces();
+
+        if self.eof() {
+          if !conds.is_empty() { self.error("unterminated %if"); }
+
+          match end {
+            Some(ch) => { self.error(fmt!("expected `%c`, got eof", ch)); }
+            None => { break; }
+          }
+        }
+
+        match end {
+          Some(ch) if ch == self.cursor => {
+            if !conds.is_empty() { self.error("unterminated %if"); }
+            self.bump();
+            break;
+          }
+          _ => {}
+        }
+
+        if self.cursor == ';' {
           self.bump();
-          break;
+
+          if !filled {
+            match self.options.empty_commands {
+              SkipEmpty => {}
+              KeepEmpty => {
+                // like other commands, only kept in taken branches
+                if vec::all(conds, |taken| *taken) { push(@empty_command()); }
+              }
+              RejectEmpty => { self.error("empty command before `;`"); }
+            }
+          }
+
+          filled = false;
+        }
+        else if self.cursor == '%' {
+          self.parse_directive(&mut conds);
         }
         else {
-          push(@self.parse_command());
+          let taken = vec::all(conds, |taken| *taken);
+          let (count, max_depth) = (self.command_count, self.max_depth);
+
+          let mark = self.mark();
+          let command = @self.parse_command();
+          self.expect_progress(mark);
+
+          // with check_stripped, commands in branches that aren't
+          // taken are parsed and then thrown away, and left out of
+          // the metrics
+          if taken { push(command); }
+          else {
+            self.command_count = count;
+            self.max_depth = max_depth;
+          }
+
+          filled = true;
+        }
+
+        // stripped commands, `;` and directives (the pragma included)
+        // all end the header
+        self.past_header = true;
+      }
+    }
+  }
+
+  // reads a `%name args` directive, leaving the cursor at the newline
+  // or comment that ends it.
+  fn scan_directive(&self) -> (~str, ~str) {
+    self.bump(); // skip the %
+    let name = self.consume(|c| char::is_alphanumeric(c) || c == '-');
+    self.parse_spaces();
+    let args = do io::with_str_writer |out| {
+      while !self.eof() && self.cursor != '\n' && !self.at_comment() {
+        out.write_char(self.cursor);
+        self.bump();
+      }
+    };
+
+    (name, str::trim(args))
+  }
+
+  fn parse_directive(&self, conds: &mut ~[bool]) {
+    let (name, args) = self.scan_directive();
+    if name != ~"ixl" { self.saw_conditional = true; }
+
+    match name {
+      ~"if" => {
+        conds.push(self.eval_condition(args));
+      }
+      ~"else" => {
+        if conds.is_empty() { self.error("%else without %if"); }
+        self.expect_no_args(name, args);
+        let taken = conds.pop();
+        conds.push(!taken);
+      }
+      ~"end" => {
+        if conds.is_empty() { self.error("%end without %if"); }
+        self.expect_no_args(name, args);
+        conds.pop();
+      }
+      ~"ixl" => {
+        if self.past_header || self.depth > 0u {
+          self.error("%ixl must come before any commands");
+        }
+
+        self.set_version(args);
+      }
+      _ => { self.error(fmt!("unknown directive `%%%s`", name)); }
+    }
+
+    if !self.options.check_stripped && !vec::all(*conds, |taken| *taken) {
+      self.skip_stripped(conds);
+    }
+  }
+
+  fn expect_no_args(&self, name: &str, args: &str) {
+    if !args.is_empty() {
+      self.error(fmt!("unexpected `%s` after %%%s", args, name));
+    }
+  }
+
+  // skips the branch of the innermost %if without parsing it, up to
+  // the %else or %end that ends it.  directives are only recognized at
+  // the start of a line here, and nothing else is tokenized, so a line
+  // starting with `%end` inside a multi-line string or block comment
+  // still ends the branch.
+  fn skip_stripped(&self, conds: &mut ~[bool]) {
+    // %if directives opened inside the skipped text
+    let mut nested = 0u;
+
+    loop {
+      self.consume(is_linespace);
+      if self.eof() { return; }
+
+      if self.cursor == '%' {
+        let (name, args) = self.scan_directive();
+
+        match name {
+          ~"if" => { nested += 1u; }
+          ~"end" if nested > 0u => { nested -= 1u; }
+          ~"else" if nested == 0u => {
+            self.expect_no_args(name, args);
+            let taken = conds.pop();
+            conds.push(!taken);
+            return;
+          }
+          ~"end" => {
+            self.expect_no_args(name, args);
+            conds.pop();
+            return;
+          }
+          _ => {}
+        }
+      }
+
+      self.consume(|c| c != '\n');
+    }
+  }
+
+  fn set_version(&self, version: &str) {
+    let n = match uint::from_str(version) {
+      Some(n) => n,
+      None => self.error(fmt!("malformed pragma `%%ixl %s`", version))
+    };
+
+    match dialect_options(n, copy self.options) {
+      Some(options) => { self.options = options; }
+      None => {
+        self.error(fmt!("unknown language version `%%ixl %s` \
+                         (this parser knows versions 1 to %u)",
+                        version, LATEST_VERSION));
+      }
+    }
+  }
+
+  fn eval_condition(&self, condition: &str) -> bool {
+    let words = str::words(condition);
+
+    if words.len() == 1u {
+      self.definition(words[0]).is_some()
+    }
+    else if words.len() == 3u && words[1] == ~"==" {
+      self.definition(words[0]) == Some(copy words[2])
+    }
+    else if words.len() == 3u && words[1] == ~"!=" {
+      self.definition(words[0]) != Some(copy words[2])
+    }
+    else {
+      self.error(fmt!("malformed condition `%%if %s`", condition))
+    }
+  }
+
+  fn definition(&self, name: &str) -> Option<~str> {
+    for self.options.defines.each |def| {
+      match *def {
+        (ref key, ref value) if str::eq_slice(*key, name) => {
+          return Some(copy *value);
         }
+        _ => {}
       }
     }
+
+    None
   }
 
   fn parse_termspaces(&self) {
     self.consume(is_termspace);
 
-    while self.cursor == '#' {
-      self.consume(|x| x != '\n');
+    while self.at_comment() {
+      self.skip_comment();
       self.consume(is_termspace);
     }
   }
 
   fn parse_string(&self) -> ~str {
     if self.cursor != '{' {
-      return self.consume(|x| !is_word_terminator(x));
+      return self.consume(|x| !self.ends_word(x));
     }
 
     do io::with_str_writer |out| {
@@ -184,17 +804,15 @@ impl Scanner {
       match self.cursor {
         '{' => {
           callback(self.cursor);
-          brace_count += 1u;
+          brace_count = self.open_brace(brace_count);
         }
         '}' => {
-          brace_count -= 1u;
-          if brace_count == 0 { break; }
+          brace_count = self.close_brace(brace_count);
+          if brace_count == 0u { break; }
           else { callback(self.cursor); }
         }
         '\\' => {
-          if self.eof() { self.error("unterminated braces"); }
-          self.bump();
-          callback(self.cursor);
+          callback(self.parse_escape());
         }
         _ => { callback(self.cursor); }
       }
@@ -203,12 +821,23 @@ impl Scanner {
       if self.eof() { self.error("unterminated braces"); }
     }
 
-    if self.eof() { self.error("unterminated braces"); }
     self.bump();
   }
 
+  // brace counting is checked rather than left to wrap around, so
+  // malformed input always ends up as a parse error.
+  fn open_brace(&self, count: uint) -> uint {
+    if count == uint::max_value { self.error("braces nested too deeply"); }
+    count + 1u
+  }
+
+  fn close_brace(&self, count: uint) -> uint {
+    if count == 0u { self.error("unbalanced `}`"); }
+    count - 1u
+  }
+
   fn bareword(&self, callback: fn(char)) {
-    while !self.eof() && !is_word_terminator(self.cursor) {
+    while !self.eof() && !self.ends_word(self.cursor) {
       callback(self.cursor);
       self.bump()
     }
@@ -225,18 +854,22 @@ impl Scanner {
 
   fn parse_bareword(&self) -> @[Term] {
     do at_vec::build |push| {
-      while !self.eof() && !is_word_terminator(self.cursor) {
+      while !self.eof() && !self.ends_word(self.cursor) {
+        let mark = self.mark();
+
         match self.cursor {
           '$' => {
             push(self.parse_interp_dollar());
           }
           _ => {
             let s = self.consume_escaped(|s| {
-              s != '$' && !is_word_terminator(s)
+              s != '$' && !self.ends_word(s)
             });
             push(String(s));
           }
         }
+
+        self.expect_progress(mark);
       }
     }
   }
@@ -258,12 +891,28 @@ impl Scanner {
     self.bump(); // consume initial open brace
     if self.eof() { self.error("unterminated braces"); }
 
+    let pieces = self.parse_interp_pieces(true);
+
+    self.bump(); // consume the closing brace
+    pieces
+  }
+
+  // the inside of a "{...} string, up to (but not including) its
+  // closing brace if `closed`, or else up to eof.
+  fn parse_interp_pieces(&self, closed: bool) -> @[Term] {
     let mut brace_count = 1u;
 
     // TODO: dedup this code with self.braces()
     do at_vec::build |push| {
       loop {
         if brace_count == 0u { break; }
+
+        if !closed && self.eof() {
+          // braces opened in the fragment must be closed in it too
+          if brace_count > 1u { self.error("unterminated braces"); }
+          break;
+        }
+
         match self.cursor {
           '$' => {
             push(self.parse_interp_dollar())
@@ -272,18 +921,27 @@ impl Scanner {
             // scan the next string segment
             let string_component = do io::with_str_writer |out| {
               loop {
-                if self.eof() { self.error("unterminated braces"); }
+                if self.eof() {
+                  if closed { self.error("unterminated braces"); }
+                  break;
+                }
+
                 match self.cursor {
-                  '{' => { 
-                    brace_count += 1u;
+                  '{' => {
+                    brace_count = self.open_brace(brace_count);
                     out.write_char('{');
                   }
                   '}' => {
-                    brace_count -= 1u;
+                    if !closed && brace_count == 1u {
+                      self.error("unbalanced `}`");
+                    }
+
+                    brace_count = self.close_brace(brace_count);
                     if brace_count == 0u { break; }
                     else { out.write_char('}'); }
                   }
                   '$' => { break; }
+                  '\\' => { out.write_char(self.parse_escape()); }
                   _ => { out.write_char(self.cursor); }
                 }
 
@@ -298,8 +956,38 @@ impl Scanner {
     }
   }
 
+  // a bareword made only of digits is a number, as long as it would
+  // read back the same way: `007`, and numbers too big for a u32,
+  // stay strings.
+  fn parse_number(&self) -> Term {
+    let digits = self.consume(char::is_digit);
+
+    // longer comment delimiters only count at the start of a word, so
+    // `42//x` is one word even with `//` comments
+    if self.eof() || self.ends_word(self.cursor) {
+      return match number_value(digits) {
+        Some(n) => NumberLiteral(n),
+        None => Interp(@[String(digits)])
+      };
+    }
+
+    // the digits only started a longer bareword
+    let rest = self.parse_bareword();
+
+    Interp(do at_vec::build |push| {
+      match rest[0] {
+        String(ref s) => { push(String(digits + *s)); }
+        _ => { push(String(copy digits)); push(copy rest[0]); }
+      }
+
+      for uint::range(1u, rest.len()) |i| { push(copy rest[i]); }
+    })
+  }
+
   fn parse_term(&self) -> Term {
     match self.cursor {
+      // `$(...)` is interpolated, like it is later in a word
+      '$' if self.peek() == '(' => { Interp(self.parse_bareword()) }
       '$' => {
         self.bump();
         Variable(self.parse_varname())
@@ -308,11 +996,23 @@ impl Scanner {
       '(' => { self.parse_subst() }
       '\'' => { self.bump(); String(self.parse_string()) }
       '"' => { self.bump(); Interp(self.parse_interp_string()) }
+      '0'..'9' => { self.parse_number() }
       _ => { Interp(self.parse_bareword()) }
     }
   }
 
   fn parse_command(&self) -> Command {
+    if self.cursor == '|' {
+      if self.options.pipes_across_newlines {
+        self.error("expected a command before `|`");
+      }
+      else {
+        self.error("expected a command before `|` \
+                    (pipelines can't continue across newlines here; \
+                    end the previous line with `|` or `\\` instead)");
+      }
+    }
+
     let target = if self.cursor == '@' {
       self.bump();
       Some(self.parse_term())
@@ -328,7 +1028,9 @@ impl Scanner {
     // look for flags
     let components = do at_vec::build |push| {
       while !self.eof() {
-        if is_word_terminator(self.cursor) { break; }
+        if self.ends_word(self.cursor) || self.at_comment() { break; }
+
+        let mark = self.mark();
 
         match self.cursor {
           '-' => {
@@ -338,21 +1040,38 @@ impl Scanner {
           _ => { push(@Argument(self.parse_term())); }
         }
 
+        self.expect_progress(mark);
         self.parse_spaces();
       }
     };
 
-    // pipes can be after comments or newlines,
-    // but not semicolons.
-    if self.cursor != ';' { self.parse_termspaces() }
+    // pipes can be after comments or newlines (unless the options
+    // say otherwise), but not semicolons.
+    if self.options.pipes_across_newlines {
+      let line = self.pos.line;
+      self.parse_linespaces();
+
+      if self.cursor == '|' && self.pos.line != line {
+        self.warn("`|` continues the pipeline from the line above; \
+                   end that line with `|` or `\\` to make this explicit");
+      }
+    }
 
-    let pipe = if self.cursor == '|' { 
+    let pipe = if self.cursor == '|' {
       self.bump();
-      self.parse_spaces();
+
+      // a trailing `|` always continues onto the next line
+      self.parse_linespaces();
+      if self.eof() || ";])".contains_char(self.cursor) {
+        self.error("expected a command after `|`");
+      }
+
       Some(@self.parse_command())
     }
     else { None };
 
+    self.command_count += 1u;
+
     Command {
       target: target,
       components: components,
@@ -361,33 +1080,97 @@ impl Scanner {
   }
 
   fn parse(&self) -> Program {
-    let commands = do at_vec::build |push| {
-      while !self.eof() {
-        self.parse_termspaces();
-        push(@self.parse_command());
-      }
+    let commands = self.parse_command_list(None);
+    Program { commands: commands, version: self.options.version }
+  }
+
+  fn parse_with_metrics(&self) -> (Program, ParseMetrics) {
+    let start = std::time::precise_time_ns();
+    let program = self.parse();
+
+    let metrics = ParseMetrics {
+      bytes: self.pos.byte,
+      commands: self.command_count,
+      max_depth: self.max_depth,
+      duration_ns: std::time::precise_time_ns() - start,
     };
 
-    Program { commands: commands }
+    (program, metrics)
+  }
+
+  // parses the whole input as though it appeared inside a block,
+  // substitution or interpolated string, without its delimiters.  a
+  // closing delimiter in the input that would end the surrounding
+  // construct early is a parse error.
+  fn parse_fragment(&self, ambient: Ambient) -> Fragment {
+    match ambient {
+      InBlock | InSubst => Commands(self.parse_command_list(None)),
+      InInterp => Pieces(self.parse_interp_pieces(false)),
+    }
+  }
+}
+
+pub pure fn number_value(digits: &str) -> Option<u32> {
+  if digits.len() > 1u && digits[0] == '0' as u8 { return None; }
+  if digits.len() > 10u { return None; }
+
+  match uint::from_str(digits) {
+    Some(n) if n <= u32::max_value as uint => Some(n as u32),
+    _ => None
+  }
+}
+
+/**
+ * Entry points
+ */
+pub fn parse_reader(reader: io::Reader) -> Program {
+  Scanner(reader).parse()
+}
+
+pub fn parse_str(source: &str) -> Program {
+  io::with_str_reader(source, parse_reader)
+}
+
+pub fn parse_file(path: &Path) -> Result<Program, ~str> {
+  match io::file_reader(path) {
+    Ok(reader) => Ok(parse_reader(reader)),
+    Err(msg) => Err(msg)
   }
 }
 
+fn empty_command() -> Command {
+  Command { target: None, components: @[], pipe: None }
+}
+
+pure fn is_hex_digit(ch: char) -> bool {
+  char::is_digit(ch) || "abcdefABCDEF".contains_char(ch)
+}
+
 pure fn is_space(ch: char) -> bool {
   " \t".contains_char(ch)
 }
 
+pure fn is_linespace(ch: char) -> bool {
+  is_space(ch) || "\n\r".contains_char(ch)
+}
+
 pure fn is_termspace(ch: char) -> bool {
-  is_space(ch) || "\n;\r".contains_char(ch)
+  is_linespace(ch) || ch == ';'
 }
 
-pure fn is_word_terminator(ch: char) -> bool {
-  is_termspace(ch) || "#])|".contains_char(ch)
+pub pure fn is_word_terminator(ch: char) -> bool {
+  is_termspace(ch) || "])|".contains_char(ch)
 }
 
 fn with_scanner<T>(s: &str, yield: fn(Scanner) -> T) -> T {
   io::with_str_reader(s, |r| yield(Scanner(r)))
 }
 
+fn with_options<T>(s: &str, options: ParserOptions,
+                   yield: fn(Scanner) -> T) -> T {
+  io::with_str_reader(s, |r| yield(scanner_with_options(r, options)))
+}
+
 #[test]
 fn test_scanner() {
   do with_scanner("hello world") |scanner| {
@@ -542,3 +1325,646 @@ fn test_interp() {
     ])])
   ));
 }
+
+#[test]
+fn test_leading_subst() {
+  let t1 = with_scanner(~"$(baz zot)", |s| s.parse_term());
+  assert(matches!(t1,
+    Interp([Subst([
+      @Command { target: None, pipe: None, components: [
+        @Argument(Interp([String(~"baz")])),
+        @Argument(Interp([String(~"zot")]))
+      ]}
+    ])])
+  ));
+
+  let t2 = with_scanner(~"$(baz).log", |s| s.parse_term());
+  assert(matches!(t2, Interp([Subst(_), String(~".log")])));
+
+  let c1 = with_scanner(~"echo $(date) $x", |s| s.parse_command());
+  assert(c1.components.len() == 3);
+  assert(matches!(*c1.components[1], Argument(Interp([Subst(_)]))));
+  assert(matches!(*c1.components[2], Argument(Variable(~"x"))));
+}
+
+#[test]
+fn test_pipe_newlines() {
+  let p1 = with_scanner(~"'foo\n# comment\n| 'bar", |s| s.parse());
+  assert(p1.commands.len() == 1);
+  assert(matches!(p1.commands[0].pipe, Some(_)));
+
+  let p2 = with_scanner(~"'foo |\n  'bar\n'baz", |s| s.parse());
+  assert(p2.commands.len() == 2);
+  match p2.commands[0].pipe {
+    Some(ref bar) => {
+      assert(matches!(*bar.components[0], Argument(String(~"bar"))));
+    }
+    _ => { fail }
+  }
+
+  let strict = ParserOptions {
+    pipes_across_newlines: false,
+    .. default_options()
+  };
+
+  let p3 = with_options(~"'foo \\\n| 'bar", copy strict, |s| s.parse());
+  assert(p3.commands.len() == 1);
+  assert(matches!(p3.commands[0].pipe, Some(_)));
+
+  let p4 = with_options(~"'foo |\n'bar", copy strict, |s| s.parse());
+  assert(p4.commands.len() == 1);
+  assert(matches!(p4.commands[0].pipe, Some(_)));
+}
+
+#[test]
+fn test_pipe_newline_warnings() {
+  do with_scanner(~"'foo\n  | 'bar |\n'baz \\\n| 'zot\n# c\n| 'quux") |s| {
+    s.parse();
+    assert(s.warnings.len() == 2u);
+    assert(s.warnings[0].pos.line == 2u && s.warnings[0].pos.col == 3u);
+    assert(s.warnings[1].pos.line == 6u && s.warnings[1].pos.col == 1u);
+  }
+
+  let strict = ParserOptions {
+    pipes_across_newlines: false,
+    .. default_options()
+  };
+
+  do with_options(~"'foo |\n'bar", strict) |s| {
+    s.parse();
+    assert(s.warnings.is_empty());
+  }
+}
+
+#[test]
+#[should_fail]
+fn test_strict_pipe_newlines() {
+  let strict = ParserOptions {
+    pipes_across_newlines: false,
+    .. default_options()
+  };
+
+  with_options(~"'foo\n| 'bar", strict, |s| s.parse());
+}
+
+#[test]
+#[should_fail]
+fn test_dangling_pipe() {
+  with_scanner(~"'foo |; 'bar", |s| s.parse());
+}
+
+#[test]
+#[should_fail]
+fn test_dangling_pipe_in_block() {
+  with_scanner(~"['foo |\n]", |s| s.parse());
+}
+
+#[test]
+#[should_fail]
+fn test_dangling_pipe_in_subst() {
+  with_scanner(~"'x ('foo |)", |s| s.parse());
+}
+
+#[test]
+fn test_empty_commands() {
+  let p1 = with_scanner(~"# just a comment\n\n# and another", |s| s.parse());
+  assert(p1.commands.len() == 0);
+
+  let p2 = with_scanner(~";;'foo ;; 'bar;", |s| s.parse());
+  assert(p2.commands.len() == 2);
+
+  let keep = ParserOptions { empty_commands: KeepEmpty, .. default_options() };
+  let p3 = with_options(~";;'foo ;; 'bar;", copy keep, |s| s.parse());
+  assert(p3.commands.len() == 5);
+  assert(p3.commands[0].components.len() == 0);
+  assert(p3.commands[1].components.len() == 0);
+  assert(p3.commands[2].components.len() == 1);
+  assert(p3.commands[3].components.len() == 0);
+  assert(p3.commands[4].components.len() == 1);
+
+  // newlines don't count as separators
+  let p4 = with_options(~"'foo\n\n;'bar", keep, |s| s.parse());
+  assert(p4.commands.len() == 2);
+}
+
+#[test]
+#[should_fail]
+fn test_reject_empty_commands() {
+  let reject = ParserOptions {
+    empty_commands: RejectEmpty,
+    .. default_options()
+  };
+
+  with_options(~"'foo;; 'bar", reject, |s| s.parse());
+}
+
+#[test]
+#[should_fail]
+fn test_unterminated_block() {
+  with_scanner(~"['foo 'bar", |s| s.parse_block());
+}
+
+#[test]
+#[should_fail]
+fn test_stray_close() {
+  with_scanner(~"'foo\n] 'bar", |s| s.parse());
+}
+
+// every printable character, in a handful of positions, must either
+// parse or fail: never hang.
+#[test]
+fn test_no_progress_fuzz() {
+  let templates = ~[~"X", ~"foo X", ~"X X", ~"[X]", ~"$(X)",
+                    ~"\"{X}", ~"'{X}", ~"@X bar", ~"foo -X", ~"foo\nX"];
+
+  for uint::range(32u, 127u) |i| {
+    let ch = str::from_char(i as char);
+
+    for templates.each |template| {
+      let source = str::replace(*template, "X", ch);
+      do task::try |move source| {
+        with_scanner(source, |s| { s.parse(); })
+      };
+    }
+  }
+}
+
+#[test]
+fn test_brace_edges() {
+  do with_scanner(~"\"{}} 'bar") |scanner| {
+    assert(matches!(scanner.parse_term(), Interp([])));
+    // the closing brace is consumed, and the stray one is left over
+    assert(scanner.cursor == '}');
+  }
+
+  do with_scanner(~"\"{a{b}c}") |scanner| {
+    assert(matches!(scanner.parse_term(), Interp([String(~"a{b}c")])));
+    assert(scanner.eof());
+  }
+
+  let c1 = with_scanner(~"'foo \"{$x} 'bar", |s| s.parse_command());
+  assert(c1.components.len() == 3);
+  assert(matches!(*c1.components[2], Argument(String(~"bar"))));
+}
+
+#[test]
+#[should_fail]
+fn test_unterminated_interp() {
+  with_scanner(~"\"{a{b}", |s| s.parse_term());
+}
+
+#[test]
+#[should_fail]
+fn test_unterminated_escape_in_braces() {
+  with_scanner(~"'{a\\", |s| s.parse_term());
+}
+
+#[test]
+fn test_positions() {
+  do with_scanner(~"ab\n\u00e9\U0001f600x") |scanner| {
+    let p = scanner.position();
+    assert(p.byte == 0u && p.line == 1u && p.col == 1u);
+
+    scanner.bump(); scanner.bump(); scanner.bump();
+    let p = scanner.position();
+    assert(scanner.cursor == '\u00e9');
+    assert(p.byte == 3u && p.line == 2u && p.col == 1u && p.utf16_col == 1u);
+
+    scanner.bump(); scanner.bump();
+    let p = scanner.position();
+    assert(scanner.cursor == 'x');
+    assert(p.byte == 9u && p.col == 3u && p.utf16_col == 4u);
+  }
+}
+
+#[test]
+fn test_fragments() {
+  let f1 = with_scanner(~"'foo; 'bar\n'baz", |s| s.parse_fragment(InBlock));
+  assert(matches!(f1, Commands(cs) => cs.len() == 3));
+
+  let f2 = with_scanner(~"a {b} $c", |s| s.parse_fragment(InInterp));
+  assert(matches!(f2,
+    Pieces([String(~"a {b} "), Variable(~"c")])
+  ));
+
+  let f3 = with_scanner(~"", |s| s.parse_fragment(InInterp));
+  assert(matches!(f3, Pieces([])));
+}
+
+#[test]
+#[should_fail]
+fn test_fragment_stray_close() {
+  with_scanner(~"'foo ] 'bar", |s| s.parse_fragment(InSubst));
+}
+
+#[test]
+#[should_fail]
+fn test_fragment_unbalanced_interp() {
+  with_scanner(~"a} b", |s| s.parse_fragment(InInterp));
+}
+
+#[test]
+fn test_fragment_unterminated_interp() {
+  let sources = ~[~"a {b", ~"a {$b", ~"{{}"];
+
+  for sources.each |source| {
+    let source = copy *source;
+    let result = do task::try |move source| {
+      with_scanner(source, |s| { s.parse_fragment(InInterp); })
+    };
+
+    assert(result.is_err());
+  }
+}
+
+#[test]
+fn test_parse_metrics() {
+  let (program, metrics) = with_scanner(
+    ~"'foo | 'bar\n'baz [$([zot])]\n",
+    |s| s.parse_with_metrics()
+  );
+
+  assert(program.commands.len() == 2);
+  assert(metrics.bytes == 28u);
+  // 'foo, 'bar, 'baz, the command inside the block, the `[zot]`
+  // inside the substitution, and zot itself
+  assert(metrics.commands == 6u);
+  assert(metrics.max_depth == 3u);
+
+  let options = ParserOptions {
+    check_stripped: true,
+    .. default_options()
+  };
+
+  let (program, metrics) = with_options(
+    ~"'a\n%if nope\n'b [[[c]]]\n%else\n'd\n%end",
+    options,
+    |s| s.parse_with_metrics()
+  );
+
+  assert(program.commands.len() == 2);
+  assert(metrics.commands == 2u);
+  assert(metrics.max_depth == 0u);
+}
+
+#[test]
+fn test_conditionals() {
+  let source = ~"'a\n\
+                 %if os == windows\n'b\n%else\n'c\n%end\n\
+                 %if debug # not defined\n'{unterminated\n%end\n\
+                 %if os != linux\n\
+                 %if debug\n'd\n%else\n'e\n%end\n\
+                 %end\n\
+                 'f";
+
+  let options = ParserOptions {
+    defines: ~[(~"os", ~"windows")],
+    .. default_options()
+  };
+
+  let program = with_options(source, options, |s| s.parse());
+  let names = do program.commands.map |c| {
+    match *c.components[0] { Argument(String(ref x)) => copy *x, _ => fail }
+  };
+
+  assert(names == ~[~"a", ~"b", ~"e", ~"f"]);
+}
+
+#[test]
+fn test_stripped_empty_commands() {
+  let options = ParserOptions {
+    check_stripped: true,
+    empty_commands: KeepEmpty,
+    .. default_options()
+  };
+
+  let program = with_options(~"'a\n%if nope\n;;\n%else\n;\n%end",
+                             options, |s| s.parse());
+  assert(program.commands.len() == 2);
+  assert(program.commands[1].components.len() == 0);
+}
+
+#[test]
+#[should_fail]
+fn test_check_stripped() {
+  let options = ParserOptions {
+    check_stripped: true,
+    .. default_options()
+  };
+
+  with_options(~"%if nope\n'{unterminated\n%end", options, |s| s.parse());
+}
+
+#[test]
+fn test_directive_args() {
+  let sources = ~[~"%if a\n%end foo", ~"%if a\n%else foo\n%end",
+                  ~"%if nope\n%end foo", ~"%if nope\n%else foo\n%end"];
+
+  for sources.each |source| {
+    let source = copy *source;
+    let result = do task::try |move source| {
+      with_scanner(source, |s| { s.parse(); })
+    };
+
+    assert(result.is_err());
+  }
+
+  // comments after a directive are fine
+  let program = with_scanner(~"%if nope\n%else # always\n'a\n%end # if",
+                             |s| s.parse());
+  assert(program.commands.len() == 1);
+}
+
+#[test]
+#[should_fail]
+fn test_unterminated_conditional() {
+  with_scanner(~"['foo\n%if nope\n%else\n'bar]", |s| s.parse());
+}
+
+#[test]
+fn test_comment_syntaxes() {
+  let options = ParserOptions {
+    line_comments: ~[~"//", ~"--"],
+    block_comments: Some((~"/*", ~"*/")),
+    .. default_options()
+  };
+
+  let source = ~"'a#b // comment\n\
+                 -- another\n\
+                 'c /* inline\n comment */ 'd\n\
+                 /* 'e */ 'f -x";
+
+  let program = with_options(source, options, |s| s.parse());
+  assert(program.commands.len() == 3);
+
+  let c1 = program.commands[0];
+  assert(c1.components.len() == 1);
+  assert(matches!(*c1.components[0], Argument(String(~"a#b"))));
+
+  // block comments can sit in the middle of a command
+  let c2 = program.commands[1];
+  assert(c2.components.len() == 2);
+  assert(matches!(*c2.components[1], Argument(String(~"d"))));
+
+  let c3 = program.commands[2];
+  assert(c3.components.len() == 2);
+  assert(matches!(*c3.components[0], Argument(String(~"f"))));
+  assert(matches!(*c3.components[1], Flag(~"x")));
+}
+
+#[test]
+fn test_dash_comments() {
+  let options = ParserOptions {
+    line_comments: ~[~"--"],
+    .. default_options()
+  };
+
+  let c1 = with_options(~"foo --bar", copy options, |s| s.parse_command());
+  assert(c1.components.len() == 1);
+
+  let c2 = with_options(~"foo -{bar} -x", options, |s| s.parse_command());
+  assert(c2.components.len() == 3);
+  assert(matches!(*c2.components[1], Flag(~"bar")));
+  assert(matches!(*c2.components[2], Flag(~"x")));
+}
+
+#[test]
+fn test_validate_options() {
+  assert(validate_options(&default_options()).is_ok());
+
+  let bad = ~[~[~""], ~[~"///"], ~[~"# "], ~[~";"], ~[~"\u00a7"]];
+
+  for bad.each |comments| {
+    let options = ParserOptions {
+      line_comments: copy *comments,
+      .. default_options()
+    };
+
+    assert(validate_options(&options).is_err());
+  }
+
+  let options = ParserOptions {
+    block_comments: Some((~"{-", ~"")),
+    .. default_options()
+  };
+
+  assert(validate_options(&options).is_err());
+}
+
+#[test]
+#[should_fail]
+fn test_bad_options() {
+  let options = ParserOptions {
+    line_comments: ~[~"rem"],
+    .. default_options()
+  };
+
+  with_options(~"foo", options, |s| s.parse());
+}
+
+#[test]
+fn test_block_comments() {
+  let source = ~"'a #* one\n\
+                 'b # a line comment *#\n\
+                 #* outer #* inner *# still outer\n'c\n*# 'd\n\
+                 'e#*x*#f";
+
+  let v2 = ParserOptions { version: 2u, .. default_options() };
+  let program = with_options(source, v2, |s| s.parse());
+  assert(program.commands.len() == 3);
+
+  let c1 = program.commands[0];
+  assert(c1.components.len() == 1);
+  assert(matches!(*c1.components[0], Argument(String(~"a"))));
+
+  let c2 = program.commands[1];
+  assert(c2.components.len() == 1);
+  assert(matches!(*c2.components[0], Argument(String(~"d"))));
+
+  // like `#`, a block comment ends the word it appears in
+  let c3 = program.commands[2];
+  assert(c3.components.len() == 2);
+  assert(matches!(*c3.components[0], Argument(String(~"e"))));
+  assert(matches!(*c3.components[1], Argument(Interp([String(~"f")]))));
+}
+
+#[test]
+#[should_fail]
+fn test_unterminated_block_comment() {
+  with_scanner(~"%ixl 2\n'a #* #* *#\n'b", |s| s.parse());
+}
+
+#[test]
+fn test_v1_banner_comments() {
+  // version 1 has no block comments, so banners are line comments
+  let source = ~"#*****\n'a\n#***** *#\n'b";
+
+  let p1 = with_scanner(copy source, |s| s.parse());
+  assert(p1.commands.len() == 2);
+
+  let p2 = with_scanner(~"%ixl 1\n" + source, |s| s.parse());
+  assert(p2.commands.len() == 2);
+
+  // other block comments aren't version 1's to take away
+  let options = ParserOptions {
+    block_comments: Some((~"/*", ~"*/")),
+    .. default_options()
+  };
+
+  let p3 = with_options(~"%ixl 1\n/* 'a */ 'b", copy options, |s| s.parse());
+  assert(p3.commands.len() == 1);
+
+  let p4 = with_options(~"%ixl 2\n#* 'a\n'b", options, |s| s.parse());
+  assert(p4.commands.len() == 1);
+}
+
+#[test]
+fn test_numbers() {
+  let n1 = with_scanner(~"42", |s| s.parse_term());
+  assert(matches!(n1, NumberLiteral(42)));
+
+  let n2 = with_scanner(~"0", |s| s.parse_term());
+  assert(matches!(n2, NumberLiteral(0)));
+
+  let n3 = with_scanner(~"4294967295", |s| s.parse_term());
+  assert(matches!(n3, NumberLiteral(4294967295)));
+
+  // leading zeros and overflow keep the original text
+  let n4 = with_scanner(~"007", |s| s.parse_term());
+  assert(matches!(n4, Interp([String(~"007")])));
+
+  let n5 = with_scanner(~"4294967296", |s| s.parse_term());
+  assert(matches!(n5, Interp([String(~"4294967296")])));
+
+  let n6 = with_scanner(~"99999999999999999999999", |s| s.parse_term());
+  assert(matches!(n6, Interp([String(~"99999999999999999999999")])));
+
+  // digits that start a longer word aren't numbers
+  let n7 = with_scanner(~"12abc", |s| s.parse_term());
+  assert(matches!(n7, Interp([String(~"12abc")])));
+
+  let n8 = with_scanner(~"1$x", |s| s.parse_term());
+  assert(matches!(n8, Interp([String(~"1"), Variable(~"x")])));
+
+  let n9 = with_scanner(~"'42", |s| s.parse_term());
+  assert(matches!(n9, String(~"42")));
+
+  let c1 = with_scanner(~"sleep 5# comment", |s| s.parse_command());
+  assert(c1.components.len() == 2);
+  assert(matches!(*c1.components[1], Argument(NumberLiteral(5))));
+
+  let options = ParserOptions {
+    line_comments: ~[~"//"],
+    .. default_options()
+  };
+
+  let c2 = with_options(~"echo 42//x a42//x", options, |s| s.parse_command());
+  assert(c2.components.len() == 3);
+  assert(matches!(*c2.components[1], Argument(Interp([String(~"42//x")]))));
+  assert(matches!(*c2.components[2], Argument(Interp([String(~"a42//x")]))));
+}
+
+#[test]
+fn test_escapes() {
+  let e1 = with_scanner(~"a\\nb\\tc\\ d\\\\", |s| s.parse_term());
+  assert(matches!(e1, Interp([String(~"a\nb\tc d\\")])));
+
+  let e2 = with_scanner(~"'{a\\r\\}b\\u{1F600}}", |s| s.parse_term());
+  assert(matches!(e2, String(~"a\r}b\U0001f600")));
+
+  let e3 = with_scanner(~"\"{\\u{41}\\$x \\{\\\"}", |s| s.parse_term());
+  assert(matches!(e3, Interp([String(~"A$x {\"")])));
+
+  let e4 = with_scanner(~"${a\\u{20}b}", |s| s.parse_term());
+  assert(matches!(e4, Variable(~"a b")));
+}
+
+#[test]
+fn test_malformed_escapes() {
+  let sources = ~[~"\\q", ~"\\u41", ~"\\u{}", ~"\\u{1234567}",
+                  ~"\\u{d800}", ~"\\u{110000}", ~"\\u{41", ~"\\"];
+
+  for sources.each |source| {
+    let source = copy *source;
+    let result = do task::try |move source| {
+      with_scanner(source, |s| { s.parse_term(); })
+    };
+
+    assert(result.is_err());
+  }
+}
+
+#[test]
+fn test_version_pragma() {
+  let p1 = with_scanner(~"'foo\n| 'bar", |s| s.parse());
+  assert(p1.version == 1u);
+  assert(p1.commands.len() == 1);
+
+  let p2 = with_scanner(~"# header\n%ixl 2\n'foo |\n'bar", |s| s.parse());
+  assert(p2.version == 2u);
+  assert(p2.commands.len() == 1);
+
+  let p3 = with_scanner(~"%ixl 1\n'foo ;; 'bar", |s| s.parse());
+  assert(p3.version == 1u);
+  assert(p3.commands.len() == 2);
+}
+
+#[test]
+fn test_version_option() {
+  let v2 = ParserOptions { version: 2u, .. default_options() };
+
+  let p1 = with_options(~"'foo |\n'bar", copy v2, |s| s.parse());
+  assert(p1.version == 2u);
+  assert(p1.commands.len() == 1);
+
+  let result = do task::try |move v2| {
+    with_options(~"'foo\n| 'bar", copy v2, |s| { s.parse(); })
+  };
+  assert(result.is_err());
+
+  for [0u, LATEST_VERSION + 1u].each |version| {
+    let options = ParserOptions { version: *version, .. default_options() };
+    assert(validate_options(&options).is_err());
+  }
+}
+
+#[test]
+fn test_bad_version_pragmas() {
+  let sources = ~[~"%ixl 7", ~"%ixl two", ~"%ixl 2\n%ixl 2",
+                  ~"'foo\n%ixl 2", ~"['foo]\n[%ixl 2]",
+                  ~"%ixl 2\n'foo\n| 'bar", ~"%ixl 2\n'foo;;", ~"[%ixl 2]",
+                  ~"%if nope\n'x\n%end\n%ixl 2", ~";\n%ixl 2",
+                  ~"%if nope\n%end\n%ixl 2"];
+
+  for sources.each |source| {
+    let source = copy *source;
+    let result = do task::try |move source| {
+      with_scanner(source, |s| { s.parse(); })
+    };
+
+    assert(result.is_err());
+  }
+}
+
+#[test]
+fn test_parse_str() {
+  let program = parse_str("'foo 'bar; 'baz");
+  assert(program.commands.len() == 2);
+  assert(program.version == 1u);
+}
+
+#[test]
+fn test_ast_eq() {
+  let p1 = parse_str("foo -a [bar $baz] | zot");
+  let p2 = parse_str("foo  -a\t[bar ${baz}] # comment\n| zot");
+  let p3 = parse_str("foo -b [bar $baz] | zot");
+
+  assert(p1 == p2);
+  assert(p1 != p3);
+
+  let t1 = with_scanner(~"\"{a $b}", |s| s.parse_term());
+  assert(t1 == Interp(@[String(~"a "), Variable(~"b")]));
+  assert(copy t1 == t1);
+
+  assert(parse_str("") == Program { commands: @[], version: 1u });
+}
@@ -0,0 +1,232 @@
+extern mod std;
+extern mod ixl;
+
+use std::json;
+use std::json::ToJson;
+
+fn usage() {
+  io::stderr().write_line("usage: ixl [run] [options] [--] <file>");
+  io::stderr().write_line("       ixl [run] [options] -e <source>");
+  io::stderr().write_line("       ixl check|fmt|json [--] <file>");
+  io::stderr().write_line("       ixl check|fmt|json -e <source>");
+  io::stderr().write_line("");
+  io::stderr().write_line("options:");
+  io::stderr().write_line("  -e, --eval <source>  use this source instead of a file");
+  io::stderr().write_line("  --env-file <env>     load environment variables first");
+  io::stderr().write_line("  --login              run the init file first");
+  io::stderr().write_line("  --no-init            don't run the init file");
+  os::set_exit_status(2);
+}
+
+// where a program comes from
+enum Source {
+  File(~str),
+  Inline(~str),
+}
+
+struct RunOptions {
+  mut eval: Option<~str>,
+  mut env_files: ~[~str],
+  mut login: bool,
+  mut no_init: bool,
+}
+
+fn main() {
+  let args = os::args();
+  let options = RunOptions {
+    eval: None,
+    env_files: ~[],
+    login: false,
+    no_init: false,
+  };
+
+  let (start, ended) = match parse_options(args, 1u, &options) {
+    Some(next) => next,
+    None => { return usage(); }
+  };
+
+  let mut i = start;
+
+  // with -e, the source stands in for the file argument. a word
+  // before the file is the command, and options can come after it
+  // too, as in `ixl run --env-file .env f.ixl`.
+  let wanted = if options.eval.is_some() { 0u } else { 1u };
+  let mut command = ~"run";
+
+  if args.len() - i > wanted {
+    command = copy args[i];
+    i += 1u;
+
+    if !ended {
+      match parse_options(args, i, &options) {
+        Some((next, _)) => { i = next; }
+        None => { return usage(); }
+      }
+    }
+  }
+
+  let rest = vec::slice(args, i, args.len());
+
+  let source = match (copy options.eval, rest.len()) {
+    (Some(source), 0u) => Inline(source),
+    (None, 1u) => File(copy rest[0]),
+    _ => { return usage(); }
+  };
+
+  let run_only = !options.env_files.is_empty() || options.login ||
+                 options.no_init;
+
+  if command != ~"run" && run_only {
+    io::stderr().write_line(fmt!("ixl: --env-file, --login and --no-init \
+                                  only apply when running a script, \
+                                  not with %s", command));
+    return os::set_exit_status(2);
+  }
+
+  match command {
+    ~"run" => { run(&source, &options); }
+    ~"check" => { check(&source); }
+    ~"fmt" => { format(&source); }
+    ~"json" => { dump_json(&source); }
+    _ => { usage(); }
+  }
+}
+
+// reads options starting at args[i], returning where they stop and
+// whether they ended with `--`, or None if one isn't understood
+fn parse_options(args: &[~str], i: uint, options: &RunOptions)
+    -> Option<(uint, bool)> {
+  let mut i = i;
+
+  while i < args.len() && args[i].starts_with("-") {
+    match args[i] {
+      // the rest are arguments, even if they start with `-`
+      ~"--" => { return Some((i + 1u, true)); }
+      ~"-e" | ~"--eval" if i + 1u < args.len() => {
+        i += 1u;
+        options.eval = Some(copy args[i]);
+      }
+      ~"--env-file" if i + 1u < args.len() => {
+        i += 1u;
+        options.env_files.push(copy args[i]);
+      }
+      ~"--login" => { options.login = true; }
+      ~"--no-init" => { options.no_init = true; }
+      _ => { return None; }
+    }
+
+    i += 1u;
+  }
+
+  Some((i, false))
+}
+
+// runs the program with the default builtins
+fn run(source: &Source, options: &RunOptions) {
+  for options.env_files.each |env| {
+    if !load_env(*env) { return; }
+  }
+
+  do load(source) |program| {
+    let mut ctx = ixl::Context(ixl::default_registry(), io::stdout());
+
+    let mut ok = true;
+
+    // the init file runs in the same context, so the script sees
+    // whatever it sets up. a missing one is fine.
+    if options.login && !options.no_init {
+      match ixl::init_file() {
+        Some(init) if os::path_exists(&init) => {
+          ok = false;
+          do load(&File(init.to_str())) |init_program| {
+            ok = eval(&mut ctx, &init_program);
+          }
+        }
+        _ => {}
+      }
+    }
+
+    if ok { eval(&mut ctx, &program); }
+  }
+}
+
+fn eval(ctx: &mut ixl::Context, program: &ixl::Program) -> bool {
+  match ctx.eval_program(program) {
+    Ok(_) => true,
+    Err(e) => {
+      io::stderr().write_line(fmt!("ixl: %s", e.to_str()));
+      os::set_exit_status(1);
+      false
+    }
+  }
+}
+
+// sets environment variables from a dotenv file
+fn load_env(path: &str) -> bool {
+  match ixl::load_dotenv(&Path(path)) {
+    Ok(vars) => {
+      for vars.each |&(name, value)| { os::setenv(name, value); }
+      true
+    }
+    Err(msg) => {
+      io::stderr().write_line(fmt!("ixl: %s", msg));
+      os::set_exit_status(1);
+      false
+    }
+  }
+}
+
+// parses the program, reporting syntax errors and warnings
+fn check(source: &Source) {
+  do with_reader(source) |reader| {
+    let scanner = ixl::Scanner(reader);
+    scanner.parse();
+
+    for scanner.warnings.each |warning| {
+      io::stderr().write_line(fmt!("ixl: warning at line %u:%u: %s",
+                                   warning.pos.line, warning.pos.col,
+                                   warning.msg));
+    }
+  }
+}
+
+// prints the program in canonical form
+fn format(source: &Source) {
+  do with_reader(source) |reader| {
+    match ixl::format_source(reader, &ixl::FormatRules()) {
+      Ok(formatted) => { io::print(formatted); }
+      Err(msg) => {
+        io::stderr().write_line(fmt!("ixl: %s", msg));
+        os::set_exit_status(1);
+      }
+    }
+  }
+}
+
+// prints the program's AST as JSON
+fn dump_json(source: &Source) {
+  do load(source) |program| {
+    io::println(json::to_pretty_str(&program.to_json()));
+  }
+}
+
+fn load(source: &Source, f: fn(ixl::Program)) {
+  do with_reader(source) |reader| {
+    f(ixl::parse_reader(reader));
+  }
+}
+
+fn with_reader(source: &Source, f: fn(io::Reader)) {
+  match *source {
+    File(ref path) => {
+      match io::file_reader(&Path(*path)) {
+        Ok(reader) => { f(reader); }
+        Err(msg) => {
+          io::stderr().write_line(fmt!("ixl: %s", msg));
+          os::set_exit_status(1);
+        }
+      }
+    }
+    Inline(ref text) => { io::with_str_reader(*text, f); }
+  }
+}